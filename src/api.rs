@@ -0,0 +1,106 @@
+use actix::prelude::*;
+use actix_web::http::header;
+use actix_web::{error, App, Error, HttpRequest, HttpResponse, Json};
+use futures::future::{self, Future};
+use identify::PlcStatus;
+use networking::ToPlcConn;
+use reload::{ManageError, ManagePlcs, PlcChange, Watcher};
+use serde_json::{self, Value};
+use settings::PlcSetting;
+use std::sync::Arc;
+use ws::WsState;
+
+type Reply = Box<Future<Item = HttpResponse, Error = Error>>;
+
+fn token(r: &HttpRequest<Arc<WsState>>) -> Option<String> {
+    let auth = r.headers().get(header::AUTHORIZATION)?.to_str().ok()?;
+    if auth.starts_with("Bearer ") {
+        Some(auth["Bearer ".len()..].trim().to_string())
+    } else {
+        None
+    }
+}
+
+fn status(state: &WsState, plc: &PlcSetting) -> Value {
+    match plc.as_plc_conn().and_then(|c| state.status.get(&c).map(|s| s.clone())) {
+        Some(PlcStatus::Running(n)) => json!({"state": "running", "version": n}),
+        Some(PlcStatus::Identifying) => json!({"state": "identifying"}),
+        Some(PlcStatus::Refused(reason)) => json!({"state": "refused", "reason": reason}),
+        Some(PlcStatus::Unreachable) | None => json!({"state": "unreachable"}),
+    }
+}
+
+fn error_response(e: &ManageError) -> HttpResponse {
+    match e {
+        ManageError::Disabled => HttpResponse::Forbidden().body(e.to_string()),
+        ManageError::Unauthorized => HttpResponse::Unauthorized()
+            .header(header::WWW_AUTHENTICATE, "Bearer")
+            .body(e.to_string()),
+        ManageError::NotFound => HttpResponse::NotFound().body(e.to_string()),
+        ManageError::Exists | ManageError::Unmanaged(_) => {
+            HttpResponse::Conflict().body(e.to_string())
+        }
+        ManageError::Invalid(errors) => HttpResponse::BadRequest().json(errors),
+        ManageError::Unwritable(_) => HttpResponse::InternalServerError().body(e.to_string()),
+    }
+}
+
+// the configured plcs with their status after the change
+fn manage(watcher: &Addr<Watcher>, r: &HttpRequest<Arc<WsState>>, change: PlcChange) -> Reply {
+    let state = r.state().clone();
+    let reply = watcher
+        .send(ManagePlcs {
+            token: token(r),
+            change,
+        })
+        .map_err(|_| error::ErrorInternalServerError("the config watcher is gone"))
+        .map(move |result| match result {
+            Ok(plcs) => {
+                let plcs: Vec<Value> = plcs
+                    .iter()
+                    .map(|plc| {
+                        let mut v = serde_json::to_value(plc).unwrap_or(Value::Null);
+                        v["status"] = status(&state, plc);
+                        v
+                    })
+                    .collect();
+                HttpResponse::Ok().json(plcs)
+            }
+            Err(e) => error_response(&e),
+        });
+    Box::new(reply)
+}
+
+fn plc_id(r: &HttpRequest<Arc<WsState>>) -> Result<(String, u16), Error> {
+    let m = r.match_info();
+    Ok((m.query::<String>("net_id")?, m.query::<u16>("port")?))
+}
+
+// /plc/ lists and adds, /plc/{net_id}/{port}/ updates and removes
+pub fn routes(app: App<Arc<WsState>>, watcher: &Addr<Watcher>) -> App<Arc<WsState>> {
+    let (list, add, update, remove) = (
+        watcher.clone(),
+        watcher.clone(),
+        watcher.clone(),
+        watcher.clone(),
+    );
+    app.resource("/plc/", move |r| {
+        r.get().f(move |r| manage(&list, r, PlcChange::List));
+        r.post().with(move |(r, plc): (HttpRequest<_>, Json<PlcSetting>)| {
+            manage(&add, &r, PlcChange::Add(plc.into_inner()))
+        });
+    }).resource("/plc/{net_id}/{port}/", move |r| {
+        r.put().with(move |(r, plc): (HttpRequest<_>, Json<PlcSetting>)| {
+            match plc_id(&r) {
+                Ok((net_id, port)) => {
+                    manage(&update, &r, PlcChange::Update(net_id, port, plc.into_inner()))
+                }
+                Err(e) => Box::new(future::err(e)),
+            }
+        });
+        r.delete().f(move |r| match plc_id(r) {
+            Ok((net_id, port)) => manage(&remove, r, PlcChange::Remove(net_id, port)),
+            Err(e) => Box::new(future::err(e)),
+        });
+    })
+}
@@ -0,0 +1,201 @@
+use actix::prelude::*;
+use byteorder::{ByteOrder, LittleEndian};
+use futures::future::{self, Either};
+use futures::{stream, Future, Stream};
+use networking::{AdsDeviceInfoReq, AdsDeviceInfoRes, AdsReadReq, Client};
+use serde_json::Value;
+use settings::Identity;
+use sha1::Sha1;
+use std::fmt;
+use std::sync::Arc;
+use types::{AdsVersion, ValueFormat};
+
+// symbol count and size of the symbol table, then the table itself
+const SYM_UPLOAD_INFO: u32 = 0xF00C;
+const SYM_UPLOAD: u32 = 0xF00B;
+
+#[derive(Debug, Clone)]
+pub enum PlcStatus {
+    Identifying,
+    Running(u32),
+    // no loaded version matches the program on the plc
+    Refused(String),
+    // the connection failed, it is tried again later
+    Unreachable,
+}
+
+pub struct Candidate {
+    pub number: u32,
+    pub version: Arc<AdsVersion>,
+    pub identity: Identity,
+}
+
+// what the plc tells about itself, only the parts some identity asks for are read
+struct Observed {
+    device: Option<AdsDeviceInfoRes>,
+    checksum: Option<String>,
+}
+
+impl fmt::Display for Observed {
+    fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+        let device = self.device.as_ref().map(|d| d.to_string());
+        write!(
+            f,
+            "device {}, symbol table sha1 {}",
+            device.as_ref().map_or("unknown", |d| d.as_str()),
+            self.checksum.as_ref().map_or("unknown", |c| c.as_str())
+        )
+    }
+}
+
+fn read(
+    client: &Addr<Client>,
+    index_group: u32,
+    index_offset: u32,
+    length: u32,
+) -> impl Future<Item = Vec<u8>, Error = ()> {
+    client
+        .send(AdsReadReq {
+            index_group,
+            index_offset,
+            length,
+        })
+        .map_err(|_| ())
+        .and_then(|r| r)
+        .and_then(|r| if r.result == 0 { Ok(r.data) } else { Err(()) })
+}
+
+fn device(client: &Addr<Client>) -> impl Future<Item = Option<AdsDeviceInfoRes>, Error = ()> {
+    client.send(AdsDeviceInfoReq).then(|r| match r {
+        Ok(Ok(ref info)) if info.result == 0 => Ok(Some(info.clone())),
+        _ => Ok(None),
+    })
+}
+
+fn checksum(client: &Addr<Client>) -> impl Future<Item = Option<String>, Error = ()> {
+    let table = client.clone();
+    read(client, SYM_UPLOAD_INFO, 0, 8)
+        .and_then(move |info| {
+            if info.len() < 8 {
+                return Either::A(future::err(()));
+            }
+            Either::B(read(&table, SYM_UPLOAD, 0, LittleEndian::read_u32(&info[4..])))
+        })
+        .then(|r| Ok(r.ok().map(|table| Sha1::from(&table).digest().to_string())))
+}
+
+fn text(v: &Value) -> String {
+    match v {
+        Value::String(ref s) => s.trim().to_string(),
+        v => v.to_string(),
+    }
+}
+
+// the value of the identity symbol as the candidate's layout decodes it
+fn symbol_value(
+    client: &Addr<Client>,
+    c: &Candidate,
+) -> impl Future<Item = Option<String>, Error = ()> {
+    let (symbol, len) = match c.version.identity_symbol {
+        Some(ref s) => s.clone(),
+        None => return Either::A(future::ok(None)),
+    };
+    let version = c.version.clone();
+    Either::B(
+        read(client, symbol.index_group, symbol.index_offset, len).then(move |r| {
+            Ok(r.ok().and_then(|data| {
+                symbol
+                    .ty
+                    .as_data_struct(&mut &data[..], &version.map, &ValueFormat::default())
+                    .ok()
+                    .map(|v| text(&v))
+            }))
+        }),
+    )
+}
+
+// why the candidate is not the running program, None if it is
+fn mismatch(c: &Candidate, observed: &Observed, value: &Option<String>) -> Option<String> {
+    let identity = &c.identity;
+    if let Some(ref expected) = identity.device {
+        let matches = observed.device.as_ref().map_or(false, |d| {
+            expected.trim() == d.name || expected.trim() == d.to_string()
+        });
+        if !matches {
+            return Some(format!("the device is not {}", expected));
+        }
+    }
+    if let Some(ref expected) = identity.symbol_checksum {
+        if observed.checksum.as_ref().map(|c| c.as_str()) != Some(expected.trim()) {
+            return Some(format!("the symbol table sha1 is not {}", expected));
+        }
+    }
+    if let Some(ref symbol) = identity.symbol {
+        let expected = identity
+            .value
+            .as_ref()
+            .map_or(c.number.to_string(), |v| v.trim().to_string());
+        if value.as_ref() != Some(&expected) {
+            let found = value.as_ref().map_or("unreadable", |v| v.as_str());
+            return Some(format!("{} is {}, not {}", symbol, found, expected));
+        }
+    }
+    None
+}
+
+// exactly one candidate has to match, the plc is refused with the reasons otherwise
+pub fn identify(
+    client: &Addr<Client>,
+    net_id: String,
+    candidates: Vec<Candidate>,
+) -> impl Future<Item = (u32, Arc<AdsVersion>), Error = String> {
+    let wants_device = candidates.iter().any(|c| c.identity.device.is_some());
+    let wants_checksum = candidates.iter().any(|c| c.identity.symbol_checksum.is_some());
+    let device = if wants_device {
+        Either::A(device(client))
+    } else {
+        Either::B(future::ok(None))
+    };
+    let checksum = if wants_checksum {
+        Either::A(checksum(client))
+    } else {
+        Either::B(future::ok(None))
+    };
+    let client = client.clone();
+    device
+        .join(checksum)
+        .and_then(move |(device, checksum)| {
+            stream::iter_ok(candidates)
+                .and_then(move |c| symbol_value(&client, &c).map(|v| (c, v)))
+                .collect()
+                .map(move |read| (Observed { device, checksum }, read))
+        })
+        .map_err(|_| "the plc did not answer".to_string())
+        .and_then(move |(observed, read)| {
+            info!("plc {}: {}", net_id, observed);
+            let mut matched = Vec::new();
+            let mut reasons = Vec::new();
+            for (c, value) in read {
+                match mismatch(&c, &observed, &value) {
+                    Some(reason) => reasons.push(format!("version {}: {}", c.number, reason)),
+                    None => matched.push(c),
+                }
+            }
+            match matched.len() {
+                1 => {
+                    let c = matched.remove(0);
+                    Ok((c.number, c.version))
+                }
+                0 => Err(format!(
+                    "no version matches the plc ({}), {}",
+                    observed,
+                    reasons.join(", ")
+                )),
+                _ => {
+                    let numbers: Vec<String> =
+                        matched.iter().map(|c| c.number.to_string()).collect();
+                    Err(format!("versions {} all match", numbers.join(", ")))
+                }
+            }
+        })
+}
@@ -142,34 +142,6 @@ impl Schema {
     }
 }
 
-pub fn merge(schema: Value, data: Value) -> Value {
-    match (schema, data) {
-        (Value::String(_), Value::String(s2)) => Value::String(s2),
-        (Value::Null, Value::Null) => Value::Null,
-        (Value::Bool(_), Value::Bool(s2)) => Value::Bool(s2),
-        (Value::Array(v), Value::Array(v2)) => Value::Array(
-            v.into_iter()
-                .zip(v2.into_iter())
-                .map(|(v1, v2)| merge(v1, v2))
-                .collect(),
-        ),
-        (Value::Object(map), Value::Object(mut map2)) => Value::Object(
-            map.into_iter()
-                .map(|(k1, v1)| {
-                    if map2.contains_key(&k1) {
-                        let v2 = map2.remove(&k1).unwrap();
-                        (k1, merge(v1, v2))
-                    } else {
-                        (k1, v1)
-                    }
-                })
-                .collect(),
-        ),
-        (Value::Number(_), Value::Number(n2)) => Value::Number(n2),
-        _ => unreachable!(),
-    }
-}
-
 pub fn merge_schemas(value1: Schema, value2: Schema) -> Schema {
     match (value1, value2) {
         (Schema::Tag(_), Schema::Tag(s)) => Schema::Tag(s),
@@ -1,4 +1,5 @@
 #![feature(nll)]
+#![cfg_attr(test, feature(test))]
 #![cfg_attr(feature = "cargo-clippy", allow(print_literal))]
 
 extern crate actix;
@@ -6,10 +7,12 @@ extern crate byteorder;
 extern crate bytes;
 extern crate futures;
 extern crate rand;
+#[macro_use]
 extern crate serde_json;
 #[macro_use]
 extern crate serde_derive;
 extern crate serde;
+extern crate sha1;
 extern crate tokio;
 extern crate tokio_codec;
 extern crate tokio_io;
@@ -21,33 +24,38 @@ extern crate log;
 extern crate chrono;
 extern crate config;
 extern crate fern;
+extern crate flate2;
+extern crate bincode;
 #[macro_use]
 extern crate nom;
 extern crate actix_web;
 extern crate chashmap;
 extern crate num_traits;
+extern crate quick_xml;
 extern crate quickxml_to_serde;
+extern crate regex;
+#[cfg(test)]
+extern crate test;
 
+mod api;
+mod identify;
 mod json_diff;
+mod migration;
 mod networking;
+mod reload;
 mod settings;
 mod types;
+mod version_diff;
 mod ws;
 mod ws_ads;
 mod xml_to_struct;
 
 use actix::Actor;
 use actix_web::{server, App, HttpRequest, Responder};
-use futures::future::Future;
-use networking::ToPlcConn;
-use std::path::Path;
+use futures::future::{self, Either, Future};
+use std::fs::File;
+use std::io::{self, BufRead, Write};
 use std::sync::{Arc, RwLock};
-use ws_ads::AdsStructMap;
-
-#[inline(always)]
-fn file_exists<T: AsRef<Path>>(path: T) -> bool {
-    path.as_ref().exists() && path.as_ref().is_file()
-}
 
 #[inline(always)]
 #[cfg_attr(feature = "cargo-clippy", allow(needless_pass_by_value))]
@@ -55,21 +63,262 @@ fn index(info: HttpRequest<Arc<ws::WsState>>) -> impl Responder {
     serde_json::to_string_pretty(&*info.state().config())
 }
 
-fn init_st_ads_to_bc<T: ToString + serde_json::value::Index>(
-    version: &types::AdsVersion,
-    k: &T,
-    d: &mut serde_json::Value,
-) -> Vec<u8> {
-    let key_guard: &String = &*version.search_index.get(&k.to_string()).unwrap();
-    let value: &types::AdsType = &*version.map.get(&*key_guard).unwrap();
-    let v = vec![0u8; value.len() as usize];
-    let data = value.as_data_struct(&mut &v[..], &version.map);
-    d[k] = data;
-    v
+// files are read with the symbol selection of the first configured version
+fn load_version(config: &settings::Setting, v: &str) -> Option<types::AdsVersion> {
+    let number = v.parse::<u32>().ok();
+    let conf = config.versions.iter().find(|(k, _)| {
+        let u: u32 = (*k).into();
+        Some(u) == number
+    });
+    let conf = match conf {
+        Some((_, c)) => c.clone(),
+        None => {
+            let mut c = config.versions.values().next()?.clone();
+            c.path = v.to_string();
+            c
+        }
+    };
+    reload::read_version(&conf, &config.cache_dir)
+}
+
+fn diff_versions(config: &settings::Setting, old: &str, new: &str, json: bool) -> i32 {
+    let (old, new) = match (load_version(config, old), load_version(config, new)) {
+        (Some(old), Some(new)) => (old, new),
+        _ => return 2,
+    };
+    let changes = version_diff::diff(&old, &new);
+    let breaking = changes.iter().filter(|c| c.breaking).count();
+    if json {
+        println!("{}", serde_json::to_string_pretty(&changes).unwrap());
+    } else {
+        for c in &changes {
+            println!("{}", c);
+        }
+        println!("{} changes, {} breaking", changes.len(), breaking);
+    }
+    if breaking > 0 {
+        1
+    } else {
+        0
+    }
+}
+
+fn print_config(config_file: &str) -> i32 {
+    match settings::effective(config_file) {
+        Ok(values) => {
+            for (path, value, origin) in values {
+                println!("{} = {}  ({})", path, value, origin);
+            }
+            0
+        }
+        Err(e) => {
+            println!("{}", e);
+            2
+        }
+    }
+}
+
+// all problems of the config at once, the versions are parsed for their roots and symbols
+fn check_config(config_file: &str) -> i32 {
+    let config = match settings::read(config_file) {
+        Ok(c) => c,
+        Err(e) => {
+            println!("{}", e);
+            return 2;
+        }
+    };
+    let mut errors = settings::validate(&config);
+    for v in config.versions.values() {
+        if std::path::Path::new(&v.path).is_file() {
+            if let Err(e) = xml_to_struct::read_version(v) {
+                errors.extend(e);
+            }
+        }
+    }
+    for e in &errors {
+        println!("{}", e);
+    }
+    println!("{} problems", errors.len());
+    if errors.is_empty() {
+        0
+    } else {
+        1
+    }
+}
+
+// every configured version or the given ones, like diff takes them
+fn check_versions(config: &settings::Setting, versions: Option<clap::Values>) -> i32 {
+    let mut versions: Vec<String> = versions
+        .into_iter()
+        .flat_map(|v| v.map(|v| v.to_string()))
+        .collect();
+    if versions.is_empty() {
+        versions = config.versions.keys().map(|n| u32::from(n).to_string()).collect();
+    }
+    let mut code = 0;
+    for v in versions {
+        let version = match load_version(config, &v) {
+            Some(version) => version,
+            None => {
+                code = 2;
+                continue;
+            }
+        };
+        let found = types::check(&version);
+        for i in &found {
+            println!("{}: {}", v, i);
+        }
+        println!("{}: {} inconsistencies", v, found.len());
+        if !found.is_empty() && code == 0 {
+            code = 1;
+        }
+    }
+    code
+}
+
+fn read_json(path: &str) -> Result<serde_json::Value, String> {
+    File::open(path)
+        .map_err(|e| e.to_string())
+        .and_then(|f| serde_json::from_reader(f).map_err(|e| e.to_string()))
+        .map_err(|e| format!("can not read {}: {}", path, e))
+}
+
+fn confirm(question: &str) -> bool {
+    print!("{} [y/N] ", question);
+    let _ = io::stdout().flush();
+    let mut answer = String::new();
+    let _ = io::stdin().lock().read_line(&mut answer);
+    answer.trim().eq_ignore_ascii_case("y")
+}
+
+// the retain data is read from the plc with the old version, or taken from a
+// backup, and written in the layout of the new version once the operator agrees
+fn migrate_retain(
+    system: &mut actix::SystemRunner,
+    config: &settings::Setting,
+    matches: &clap::ArgMatches,
+    old: &str,
+    new: &str,
+) -> i32 {
+    let (old, new) = match (load_version(config, old), load_version(config, new)) {
+        (Some(old), Some(new)) => (old, new),
+        _ => return 2,
+    };
+    let retains = (migration::Retain::of(&old), migration::Retain::of(&new));
+    let (old_retain, new_retain) = match retains {
+        (Ok(old), Ok(new)) => (old, new),
+        (Err(e), _) | (_, Err(e)) => {
+            error!("{}", e);
+            return 2;
+        }
+    };
+    let renames = match matches.value_of("mapping") {
+        Some(path) => read_json(path).and_then(|v| migration::Renames::from_value(&v)),
+        None => Ok(migration::Renames::default()),
+    };
+    let renames = match renames {
+        Ok(r) => r,
+        Err(e) => {
+            error!("{}", e);
+            return 2;
+        }
+    };
+    let backup = match matches.value_of("retain").map(read_json) {
+        Some(Ok(data)) => match old_retain.encode(&data) {
+            Ok(mem) => Some(mem),
+            Err(errors) => {
+                for e in errors {
+                    error!("{}", e);
+                }
+                return 2;
+            }
+        },
+        Some(Err(e)) => {
+            error!("{}", e);
+            return 2;
+        }
+        None => None,
+    };
+    let net_id = matches.value_of("plc").unwrap();
+    let plc = match config.plc.iter().find(|p| p.ams_net_id == net_id) {
+        Some(p) => p,
+        None => {
+            error!("plc {} is not configured", net_id);
+            return 2;
+        }
+    };
+    let addr = (plc.ip.as_str(), plc.tcp_port);
+    let client = networking::create_client(addr, plc, config.source(plc));
+    let read = |client: &actix::Addr<networking::Client>, symbol: &types::Symbol, length| {
+        client
+            .send(networking::AdsReadReq {
+                index_group: symbol.index_group,
+                index_offset: symbol.index_offset,
+                length,
+            })
+            .map_err(|_| error!("the plc did not answer"))
+            .and_then(|r| r)
+            .map(|r| r.data)
+    };
+    let old_data = client.and_then(|client| {
+        let mem = match backup {
+            Some(mem) => Either::A(future::ok(mem)),
+            None => Either::B(read(&client, &old_retain.symbol, old_retain.len())),
+        };
+        mem.map(|mem| (client, mem))
+    });
+    let (client, mem) = match system.block_on(old_data) {
+        Ok(r) => r,
+        Err(_) => return 2,
+    };
+    if let Ok(data) = old_retain.decode(&mem, &types::ValueFormat::default()) {
+        let path = format!("retain_{}_{}.json", plc.ams_net_id, old_retain.name);
+        match File::create(&path).map(|f| serde_json::to_writer_pretty(f, &data)) {
+            Ok(Ok(())) => println!("old retain data saved to {}", path),
+            _ => warn!("can not save the old retain data to {}", path),
+        }
+    }
+    let migration = match migration::migrate(&old_retain, &new_retain, &mem, &renames) {
+        Ok(m) => m,
+        Err(e) => {
+            error!("{}", e);
+            return 2;
+        }
+    };
+    println!("{}", migration);
+    let question = format!(
+        "write the migrated retain data to {}, which has to run the new version now?",
+        plc.ams_net_id
+    );
+    if !confirm(&question) {
+        println!("nothing written");
+        return 1;
+    }
+    client.do_send(networking::AdsWriteReq {
+        index_group: new_retain.symbol.index_group,
+        index_offset: new_retain.symbol.index_offset,
+        length: migration.memory.len() as u32,
+        data: migration.memory.clone(),
+    });
+    // requests are answered in order, so reading back also waits for the write
+    match system.block_on(read(&client, &new_retain.symbol, new_retain.len())) {
+        Ok(ref data) if *data == migration.memory => {
+            println!("retain data written and verified");
+            if migration.is_complete() {
+                0
+            } else {
+                1
+            }
+        }
+        _ => {
+            error!("the retain data read back differs from the written one");
+            2
+        }
+    }
 }
 
 fn main() {
-    let system = actix::System::new("adsserver");
+    let mut system = actix::System::new("adsserver");
 
     let matches: clap::ArgMatches = clap_app!(adsserver =>
         (version: "1.0")
@@ -78,6 +327,28 @@ fn main() {
         (@arg CONFIG: -c #{1,2} "Sets a custom config file")
         (@arg INPUT: "Sets the input directory to use")
         (@arg debug: -v ... "Sets the level of debugging information")
+        (@arg compare_parsers: --("compare-parsers")
+            "Reads every version with both tpy parsers, compares and times them")
+        (@arg diff: --diff #{2,2} value_names(&["OLD", "NEW"])
+            "Compares the layouts of two versions, given by number or as files, \
+             and fails on breaking changes")
+        (@arg json: --json requires[diff] "Prints the diff as json")
+        (@arg check_types: --("check-types") +takes_value min_values(0) value_name("VERSION")
+            "Checks the type definitions of the given or all configured versions")
+        (@arg check_config: --("check-config")
+            "Validates the config and the symbol selection of every version")
+        (@arg print_effective_config: --("print-effective-config")
+            "Prints every setting after merging the config file, plc_dir and APP_* \
+             variables, with where it came from")
+        (@arg build_cache: --("build-cache")
+            "Parses every configured version and writes its cache")
+        (@arg migrate: --migrate #{2,2} value_names(&["OLD", "NEW"]) requires[plc]
+            "Migrates the retain data of a plc from one version to another")
+        (@arg plc: --plc +takes_value value_name("NET_ID") "The plc to migrate")
+        (@arg mapping: --mapping +takes_value value_name("FILE") requires[migrate]
+            "Json object of old paths and their new paths, null drops a field")
+        (@arg retain: --retain +takes_value value_name("FILE") requires[migrate]
+            "Takes the old retain data from a saved json file instead of the plc")
     ).get_matches();
     let log_level = match matches.occurrences_of("debug") {
         0 => (log::LevelFilter::Error, log::LevelFilter::Warn),
@@ -107,76 +378,95 @@ fn main() {
         .apply()
         .unwrap();
 
-    let mut settings = config::Config::default();
     let config_file = matches.value_of("CONFIG").unwrap_or("config.json");
-    settings
-        .merge(config::File::with_name(config_file))
-        .unwrap()
-        .merge(config::Environment::with_prefix("APP"))
-        .unwrap();
-    let config = settings.try_into::<settings::Setting>().unwrap();
+    if matches.is_present("print_effective_config") {
+        std::process::exit(print_config(config_file));
+    }
+    if matches.is_present("check_config") {
+        std::process::exit(check_config(config_file));
+    }
+    let config = match settings::load(config_file) {
+        Ok(c) => c,
+        Err(errors) => {
+            for e in errors {
+                error!("{}", e);
+            }
+            std::process::exit(2);
+        }
+    };
+    if matches.is_present("compare_parsers") {
+        let equal = config
+            .versions
+            .values()
+            .fold(true, |equal, v| xml_to_struct::compare_parsers(v, 5) && equal);
+        std::process::exit(if equal { 0 } else { 1 });
+    }
+    if matches.is_present("build_cache") {
+        let built = config.versions.values().fold(true, |built, v| {
+            match xml_to_struct::build_cache(v, &config.cache_dir) {
+                Ok(path) => {
+                    println!("{}: {}", v.path, path.display());
+                    built
+                }
+                Err(e) => {
+                    println!("{}: {}", v.path, e);
+                    false
+                }
+            }
+        });
+        std::process::exit(if built { 0 } else { 1 });
+    }
+    if matches.is_present("check_types") {
+        let code = check_versions(&config, matches.values_of("check_types"));
+        std::process::exit(code);
+    }
+    if let Some(mut versions) = matches.values_of("diff") {
+        let (old, new) = (versions.next().unwrap(), versions.next().unwrap());
+        std::process::exit(diff_versions(&config, old, new, matches.is_present("json")));
+    }
+    if let Some(mut versions) = matches.values_of("migrate") {
+        let (old, new) = (versions.next().unwrap(), versions.next().unwrap());
+        let code = migrate_retain(&mut system, &config, &matches, old, new);
+        std::process::exit(code);
+    }
     let sps_types: chashmap::CHashMap<u32, _> = config
         .versions
         .iter()
-        .filter_map(|version| {
-            let p: &Path = version.1.path.as_ref();
-            if file_exists(&p) {
-                let u: u32 = version.0.into();
-                Some((u, Arc::new(xml_to_struct::read_tpy(version.1))))
-            } else {
-                error!("version file {:?} does not exist", p);
-                None
-            }
+        .filter_map(|(n, v)| {
+            reload::read_version(v, &config.cache_dir).map(|v| (n.into(), Arc::new(v)))
         })
         .collect();
-    let sender: chashmap::CHashMap<_, _> = config
-        .plc
-        .iter()
-        .map(move |plc| {
-            let version = &*sps_types.get(&plc.version).expect("unknown version");
-            let mkey = version
-                .search_index
-                .get(&"ST_ADS_TO_BC".to_string())
-                .unwrap();
-            let rkey = version
-                .search_index
-                .get(&"ST_RETAIN_DATA".to_string())
-                .unwrap();
-
-            let m = AdsStructMap {
-                st_ads_to_bc: version.symbols.get(&*mkey).unwrap().clone(),
-                st_retain_data: version.symbols.get(&*rkey).unwrap().clone(),
-            };
-            let mut data = serde_json::Value::Object(serde_json::Map::new());
-            let mem = ws_ads::AdsMemory {
-                ST_ADS_TO_BC: init_st_ads_to_bc(&version, &"ST_ADS_TO_BC", &mut data),
-                ST_ADS_FROM_BC: init_st_ads_to_bc(&version, &"ST_ADS_FROM_BC", &mut data),
-                ST_RETAIN_DATA: init_st_ads_to_bc(&version, &"ST_RETAIN_DATA", &mut data),
-                data,
-            };
-            let conn = (plc.ams_net_id.clone(), plc.ams_port).as_plc_conn();
-            let client_addr = networking::create_client(
-                (plc.ip.as_str(), 48898),
-                &conn,
-                &("172.16.21.2.1.1", 801),
-            ).wait()
-                .unwrap();
-            (
-                conn,
-                ws_ads::AdsToWsMultiplexer::new(client_addr, mem, version.clone(), m).start(),
-            )
-        })
-        .collect();
-    let ws_state = Arc::new(ws::WsState::new(RwLock::new(config.plc), sender));
+    let ws_state = Arc::new(ws::WsState::new(
+        RwLock::new(config.plc.clone()),
+        chashmap::CHashMap::new(),
+        sps_types,
+    ));
+    for plc in &config.plc {
+        let source = config.source(plc);
+        let connect = reload::connect(plc, ws_state.clone(), &config.versions, source);
+        let _ = system.block_on(connect);
+    }
+    let bind = config.bind.clone();
+    let watcher = reload::Watcher::new(config_file, ws_state.clone(), config).start();
 
-    server::new(move || {
+    let mut server = server::new(move || {
         App::with_state(ws_state.clone())
             .middleware(actix_web::middleware::Logger::default())
             .resource("/ws/{net_id}/{port}/", |r| r.with(ws::Ws::ws_index))
+            .resource("/meta/{net_id}/{port}/", |r| r.with(ws::meta_index))
             .resource("/", |r| r.with(index))
-    }).bind("127.0.0.1:8000")
-        .unwrap()
-        .start();
+            .configure(|app| api::routes(app, &watcher))
+    });
+    for addr in &bind {
+        server = match server.bind(addr) {
+            Ok(s) => s,
+            Err(e) => {
+                error!("can not listen on {}: {}", addr, e);
+                std::process::exit(1);
+            }
+        };
+    }
+    server.start();
 
     let _ = system.run();
 }
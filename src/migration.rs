@@ -0,0 +1,321 @@
+use chashmap::ReadGuard;
+use serde_json::Value;
+use std::collections::HashSet;
+use std::fmt;
+use types::{
+    insert_path, path_segments, AdsType, AdsVersion, ArrayFormat, CodecError, RangePolicy,
+    RootRole, Symbol, TimeFormat, ValueFormat,
+};
+
+// arrays keyed by plc index so elements keep their index when the bounds change
+fn migration_format() -> ValueFormat {
+    ValueFormat {
+        arrays: ArrayFormat::Indexed,
+        time: TimeFormat::Raw,
+        ..ValueFormat::default()
+    }
+}
+
+// old paths and what they are called in the new version, null drops a field on purpose
+#[derive(Debug, Default)]
+pub struct Renames(Vec<(Vec<String>, Option<Vec<String>>)>);
+
+fn segments(path: &str) -> Vec<String> {
+    path_segments(path).iter().map(|s| s.to_string()).collect()
+}
+
+fn display(path: &[String]) -> String {
+    let mut s = String::new();
+    for segment in path {
+        if segment.parse::<i64>().is_ok() {
+            s += &format!("[{}]", segment);
+        } else {
+            if !s.is_empty() {
+                s.push('.');
+            }
+            s += segment;
+        }
+    }
+    s
+}
+
+impl Renames {
+    // a json object like {"stOld.iValue": "stNew.iValue", "aUnused": null}
+    pub fn from_value(v: &Value) -> Result<Self, String> {
+        let o = match v {
+            Value::Object(ref o) => o,
+            _ => return Err("the mapping has to be an object of paths".to_string()),
+        };
+        o.iter()
+            .map(|(k, v)| match v {
+                Value::String(ref s) => Ok((segments(k), Some(segments(s)))),
+                Value::Null => Ok((segments(k), None)),
+                v => Err(format!("{} is no path for {}", v, k)),
+            })
+            .collect::<Result<_, _>>()
+            .map(Renames)
+    }
+
+    // the longest renamed prefix wins, the rest of the path is kept
+    fn apply(&self, path: &[String]) -> Option<Vec<String>> {
+        let rename = self
+            .0
+            .iter()
+            .filter(|(from, _)| path.starts_with(from))
+            .max_by_key(|(from, _)| from.len());
+        match rename {
+            Some((from, Some(to))) => Some(to.iter().chain(&path[from.len()..]).cloned().collect()),
+            Some((_, None)) => None,
+            None => Some(path.to_vec()),
+        }
+    }
+}
+
+// the retain root of a version, it has to be a symbol to be read and written
+pub struct Retain<'a> {
+    pub name: String,
+    pub symbol: Symbol,
+    version: &'a AdsVersion,
+    ty: ReadGuard<'a, String, AdsType>,
+}
+
+#[cfg_attr(feature = "cargo-clippy", allow(len_without_is_empty))]
+impl<'a> Retain<'a> {
+    pub fn of(version: &'a AdsVersion) -> Result<Self, CodecError> {
+        let name = version
+            .root(RootRole::Retain)
+            .ok_or_else(|| CodecError::not_found("the roots of the version").field("retain"))?
+            .to_string();
+        let symbol = version
+            .symbols
+            .get(&name)
+            .map(|s| s.clone())
+            .ok_or_else(|| CodecError::not_found("the symbols").field(&name))?;
+        let key = version
+            .search_index
+            .get(&name)
+            .map(|k| k.clone())
+            .ok_or_else(|| CodecError::unknown_type(&name))?;
+        let ty = version
+            .map
+            .get(&key)
+            .ok_or_else(|| CodecError::unknown_type(&key))?;
+        Ok(Retain {
+            name,
+            symbol,
+            version,
+            ty,
+        })
+    }
+
+    pub fn len(&self) -> u32 {
+        self.ty.len()
+    }
+
+    pub fn decode(&self, mem: &[u8], format: &ValueFormat) -> Result<Value, CodecError> {
+        self.ty
+            .as_data_struct(&mut &mem[..], &self.version.map, format)
+            .map_err(|e| e.field(&self.name))
+    }
+
+    // a backup in any array format, fields it does not name keep their initial value
+    pub fn encode(&self, data: &Value) -> Result<Vec<u8>, Vec<CodecError>> {
+        let mut mem = self.initial().map_err(|e| vec![e])?;
+        let format = migration_format();
+        self.ty
+            .patch(data, &mut mem, &self.version.map, RangePolicy::Reject, format)
+            .map(|_| mem)
+            .map_err(|errors| errors.into_iter().map(|e| e.field(&self.name)).collect())
+    }
+
+    fn initial(&self) -> Result<Vec<u8>, CodecError> {
+        let default = self.symbol.metadata.default.as_ref();
+        self.ty.default_bytes(&self.version.map, default)
+    }
+}
+
+#[derive(Debug)]
+pub struct Unmapped {
+    pub path: String,
+    pub value: Value,
+    pub reason: String,
+}
+
+#[derive(Debug)]
+pub struct Migration {
+    pub old_root: String,
+    pub new_root: String,
+    // the retain root in the layout of the new version
+    pub memory: Vec<u8>,
+    pub carried: usize,
+    pub renamed: usize,
+    pub dropped: Vec<String>,
+    pub unmapped: Vec<Unmapped>,
+    // fields of the new version no old value was found for
+    pub initial: Vec<String>,
+}
+
+impl Migration {
+    pub fn is_complete(&self) -> bool {
+        self.unmapped.is_empty()
+    }
+}
+
+impl fmt::Display for Migration {
+    fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+        for path in &self.dropped {
+            writeln!(f, "dropped  {}", path)?;
+        }
+        for u in &self.unmapped {
+            writeln!(f, "unmapped {} = {}: {}", u.path, u.value, u.reason)?;
+        }
+        for path in &self.initial {
+            writeln!(f, "initial  {}", path)?;
+        }
+        write!(
+            f,
+            "{} -> {}: {} fields carried over ({} renamed), {} dropped, {} unmapped, {} initial",
+            self.old_root,
+            self.new_root,
+            self.carried,
+            self.renamed,
+            self.dropped.len(),
+            self.unmapped.len(),
+            self.initial.len()
+        )
+    }
+}
+
+fn leaves(v: &Value, path: &mut Vec<String>, out: &mut Vec<(Vec<String>, Value)>) {
+    match v {
+        Value::Object(ref o) => for (k, v) in o {
+            path.push(k.to_string());
+            leaves(v, path, out);
+            path.pop();
+        },
+        v => out.push((path.clone(), v.clone())),
+    }
+}
+
+// every field of the old data is patched on its own into the initial value of
+// the new root, so a field that does not fit is reported and the rest is kept
+pub fn migrate(
+    old: &Retain,
+    new: &Retain,
+    mem: &[u8],
+    renames: &Renames,
+) -> Result<Migration, CodecError> {
+    let format = migration_format();
+    let mut old_leaves = Vec::new();
+    leaves(&old.decode(mem, &format)?, &mut Vec::new(), &mut old_leaves);
+    let mut memory = new.initial()?;
+    let mut new_leaves = Vec::new();
+    leaves(&new.decode(&memory, &format)?, &mut Vec::new(), &mut new_leaves);
+    let mut migration = Migration {
+        old_root: old.name.clone(),
+        new_root: new.name.clone(),
+        memory: Vec::new(),
+        carried: 0,
+        renamed: 0,
+        dropped: Vec::new(),
+        unmapped: Vec::new(),
+        initial: Vec::new(),
+    };
+    let mut mapped = HashSet::new();
+    for (path, value) in old_leaves {
+        let target = match renames.apply(&path) {
+            Some(target) => target,
+            None => {
+                migration.dropped.push(display(&path));
+                continue;
+            }
+        };
+        let mut patch = Value::Null;
+        insert_path(&mut patch, &display(&target), value.clone());
+        match new.ty.patch(&patch, &mut memory, &new.version.map, RangePolicy::Reject, format) {
+            Ok(_) => {
+                migration.carried += 1;
+                if target != path {
+                    migration.renamed += 1;
+                }
+                mapped.insert(target);
+            }
+            Err(errors) => {
+                let reasons: Vec<String> = errors.iter().map(|e| e.to_string()).collect();
+                migration.unmapped.push(Unmapped {
+                    path: display(&path),
+                    value,
+                    reason: reasons.join(", "),
+                });
+            }
+        }
+    }
+    migration.initial = new_leaves
+        .into_iter()
+        .filter(|(path, _)| !mapped.contains(path))
+        .map(|(path, _)| display(&path))
+        .collect();
+    migration.memory = memory;
+    Ok(migration)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use types::testing::{prop, version};
+    use types::AdsPlcType;
+
+    #[test]
+    fn the_longest_rename_wins_and_null_drops() {
+        let renames = Renames::from_value(&json!({
+            "st": "stNew",
+            "st.a": "x",
+            "st.b": null,
+        })).unwrap();
+        assert_eq!(renames.apply(&segments("st.a.i")), Some(segments("x.i")));
+        assert_eq!(renames.apply(&segments("st.c")), Some(segments("stNew.c")));
+        assert_eq!(renames.apply(&segments("st.b.i")), None);
+        assert_eq!(renames.apply(&segments("other")), Some(segments("other")));
+        assert!(Renames::from_value(&json!({"st": 1})).is_err());
+    }
+
+    #[test]
+    fn every_field_is_accounted_for() {
+        let old = AdsType::Struct {
+            name: "RETAIN".to_string(),
+            bit_size: 80,
+            properties: vec![
+                prop("nKeep", 0, AdsPlcType::Int(None)),
+                prop("nOld", 16, AdsPlcType::Int(None)),
+                prop("nGone", 32, AdsPlcType::Int(None)),
+                prop("nBig", 48, AdsPlcType::DInt(None)),
+            ],
+        };
+        let new = AdsType::Struct {
+            name: "RETAIN".to_string(),
+            bit_size: 64,
+            properties: vec![
+                prop("nKeep", 0, AdsPlcType::Int(None)),
+                prop("nNew", 16, AdsPlcType::Int(None)),
+                prop("nBig", 32, AdsPlcType::Int(None)),
+                prop("nFresh", 48, AdsPlcType::Int(None)),
+            ],
+        };
+        let old = version(vec![("RETAIN", old)], &[("RETAIN", RootRole::Retain)]);
+        let new = version(vec![("RETAIN", new)], &[("RETAIN", RootRole::Retain)]);
+        let (old, new) = (Retain::of(&old).unwrap(), Retain::of(&new).unwrap());
+        let renames = Renames::from_value(&json!({"nOld": "nNew", "nGone": null})).unwrap();
+        let mem = [1, 0, 2, 0, 3, 0, 0xa0, 0x86, 0x01, 0x00];
+        let m = migrate(&old, &new, &mem, &renames).unwrap();
+        assert_eq!((m.carried, m.renamed), (2, 1));
+        assert_eq!(m.dropped, vec!["nGone".to_string()]);
+        assert_eq!(m.unmapped.len(), 1);
+        assert_eq!(m.unmapped[0].path, "nBig");
+        assert_eq!(m.unmapped[0].value, json!(100_000));
+        let mut initial = m.initial.clone();
+        initial.sort();
+        assert_eq!(initial, vec!["nBig".to_string(), "nFresh".to_string()]);
+        assert!(!m.is_complete());
+        assert_eq!(m.memory, vec![1, 0, 2, 0, 0, 0, 0, 0]);
+    }
+}
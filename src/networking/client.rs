@@ -43,7 +43,8 @@ pub struct AdsClient {
     source: [u8; 8],
     target: [u8; 8],
     ws_ads: Option<Addr<AdsToWsMultiplexer>>,
-    request_map: HashMap<u32, oneshot::Sender<AmsTcpHeader<codec::AdsReadRes>>>,
+    // answers by invoke id, each request picks its own packet out again
+    request_map: HashMap<u32, oneshot::Sender<AdsPacket>>,
     write_request_sender: Option<mpsc::Sender<codec::AdsWriteReq>>,
 }
 
@@ -77,6 +78,12 @@ impl Actor for AdsClient {
 }
 
 impl AdsClient {
+    fn respond(&mut self, inv_id: u32, packet: AdsPacket) {
+        if let Some(tx) = self.request_map.remove(&inv_id) {
+            let _ = tx.send(packet);
+        }
+    }
+
     fn gen_request<T>(&self, command_id: u16, state_flags: u16, data: T) -> (AmsTcpHeader<T>, u32)
     where
         T: AdsCommand,
@@ -145,8 +152,8 @@ impl StreamHandler<codec::AdsPacket, io::Error> for AdsClient {
                 self.framed.write(codec::AdsPacket::ReadRes(r.gen_res()));
             }
             ReadRes(r) => {
-                let rres = self.request_map.remove(&r.header.inv_id).unwrap();
-                let _ = rres.send(r);
+                let inv_id = r.header.inv_id;
+                self.respond(inv_id, ReadRes(r));
                 //send_to_ws(&self.ws_ads, AdsClientToWs::ReadResult(r));
             }
             WriteReq(w) => {
@@ -158,6 +165,13 @@ impl StreamHandler<codec::AdsPacket, io::Error> for AdsClient {
                 //send_to_ws(&self.ws_ads, AdsClientToWs::WriteData(w));
             }
             WriteRes(_w) => {}
+            DeviceInfoReq(r) => {
+                self.framed.write(codec::AdsPacket::DeviceInfoRes(r.gen_res()));
+            }
+            DeviceInfoRes(r) => {
+                let inv_id = r.header.inv_id;
+                self.respond(inv_id, DeviceInfoRes(r));
+            }
         }
         //send_to_ws(&self.ws_clients, msg);
     }
@@ -175,7 +189,40 @@ impl Handler<codec::AdsReadReq> for AdsClient {
         let (tx, rx) = oneshot();
         self.request_map.insert(inv, tx);
         self.framed.write(AdsPacket::ReadReq(req));
-        Box::new(rx.map_err(|_| println!("error")).map(|f| f.header.data))
+        Box::new(rx.map_err(|_| error!("the read request was dropped")).and_then(|p| match p {
+            AdsPacket::ReadRes(r) => Ok(r.header.data),
+            _ => Err(()),
+        }))
+    }
+}
+
+impl Handler<codec::AdsDeviceInfoReq> for AdsClient {
+    type Result = Box<Future<Item = codec::AdsDeviceInfoRes, Error = ()>>;
+
+    fn handle(&mut self, msg: codec::AdsDeviceInfoReq, _: &mut Self::Context) -> Self::Result {
+        let (req, inv) = self.gen_request(1, 4, msg);
+        let (tx, rx) = oneshot();
+        self.request_map.insert(inv, tx);
+        self.framed.write(AdsPacket::DeviceInfoReq(req));
+        Box::new(rx.map_err(|_| ()).and_then(|p| match p {
+            AdsPacket::DeviceInfoRes(r) => Ok(r.header.data),
+            _ => Err(()),
+        }))
+    }
+}
+
+// closes the connection, for a plc that is not served
+pub struct Disconnect;
+
+impl Message for Disconnect {
+    type Result = ();
+}
+
+impl Handler<Disconnect> for AdsClient {
+    type Result = ();
+
+    fn handle(&mut self, _: Disconnect, ctx: &mut Self::Context) -> Self::Result {
+        ctx.stop();
     }
 }
 
@@ -12,6 +12,8 @@ pub enum AdsPacket {
     WriteRes(AmsTcpHeader<types::AdsWriteRes>),
     ReadReq(AmsTcpHeader<types::AdsReadReq>),
     ReadRes(AmsTcpHeader<types::AdsReadRes>),
+    DeviceInfoReq(AmsTcpHeader<types::AdsDeviceInfoReq>),
+    DeviceInfoRes(AmsTcpHeader<types::AdsDeviceInfoRes>),
 }
 
 impl Message for AdsPacket {
@@ -37,16 +39,22 @@ impl Decoder for AdsClientCodec {
         if src_len >= size as usize + 6 {
             let c_id = LittleEndian::read_u16(&p[6 + 16..]);
             let s_flag = LittleEndian::read_u16(&p[24..]);
-            let mut b = io::Cursor::new(src);
+            // only this packet is taken, the next one may already be in the buffer
+            let mut b = io::Cursor::new(src.split_to(size as usize + 6));
             let r = match (c_id, s_flag) {
                 (3, 4) => Some(AdsPacket::WriteReq(AmsTcpHeader::from_buf(&mut b))),
                 (3, 5) => Some(AdsPacket::WriteRes(AmsTcpHeader::from_buf(&mut b))),
                 (2, 4) => Some(AdsPacket::ReadReq(AmsTcpHeader::from_buf(&mut b))),
                 (2, 5) => Some(AdsPacket::ReadRes(AmsTcpHeader::from_buf(&mut b))),
+                (1, 4) => Some(AdsPacket::DeviceInfoReq(AmsTcpHeader::from_buf(&mut b))),
+                (1, 5) => Some(AdsPacket::DeviceInfoRes(AmsTcpHeader::from_buf(&mut b))),
                 _ => None,
             };
-            b.into_inner().clear();
-            Ok(r)
+            match r {
+                Some(r) => Ok(Some(r)),
+                // unknown commands are skipped
+                None => self.decode(src),
+            }
         } else {
             Ok(None)
         }
@@ -75,6 +83,14 @@ impl Encoder for AdsClientCodec {
                 dst.reserve(r.size());
                 dst.put(r.into_buf());
             }
+            AdsPacket::DeviceInfoReq(r) => {
+                dst.reserve(r.size());
+                dst.put(r.into_buf());
+            }
+            AdsPacket::DeviceInfoRes(r) => {
+                dst.reserve(r.size());
+                dst.put(r.into_buf());
+            }
         }
         Ok(())
     }
@@ -1,6 +1,7 @@
 use actix::Message;
 use byteorder::{LittleEndian, WriteBytesExt};
 use bytes::{Buf, IntoBuf};
+use std::fmt;
 use std::io;
 
 pub trait AdsCommand: IntoBuf + Clone {
@@ -196,6 +197,102 @@ impl IntoBuf for AdsReadRes {
     }
 }
 
+// ReadDeviceInfo has no data, the answer names the runtime and its version
+#[derive(Debug, Clone)]
+pub struct AdsDeviceInfoReq;
+
+impl Message for AdsDeviceInfoReq {
+    type Result = Result<AdsDeviceInfoRes, ()>;
+}
+
+#[derive(Debug, Clone)]
+pub struct AdsDeviceInfoRes {
+    pub result: u32,
+    pub major: u8,
+    pub minor: u8,
+    pub build: u16,
+    pub name: String,
+}
+
+impl fmt::Display for AdsDeviceInfoRes {
+    fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+        write!(f, "{} {}.{}.{}", self.name, self.major, self.minor, self.build)
+    }
+}
+
+impl AdsCommand for AdsDeviceInfoReq {
+    type Result = AdsDeviceInfoRes;
+    fn size(&self) -> usize {
+        0
+    }
+
+    fn from_buf(_: &mut impl Buf) -> Self {
+        AdsDeviceInfoReq
+    }
+
+    fn gen_res(&self) -> Self::Result {
+        AdsDeviceInfoRes {
+            result: 1793,
+            major: 0,
+            minor: 0,
+            build: 0,
+            name: String::new(),
+        }
+    }
+}
+
+impl IntoBuf for AdsDeviceInfoReq {
+    type Buf = io::Cursor<Vec<u8>>;
+
+    fn into_buf(self) -> Self::Buf {
+        io::Cursor::new(Vec::new())
+    }
+}
+
+impl AdsCommand for AdsDeviceInfoRes {
+    type Result = AdsDeviceInfoReq;
+    fn size(&self) -> usize {
+        24
+    }
+
+    fn from_buf(r: &mut impl Buf) -> Self {
+        let result = r.get_u32_le();
+        let major = r.get_u8();
+        let minor = r.get_u8();
+        let build = r.get_u16_le();
+        // the name is padded with zeros to 16 bytes
+        let name = get_vec(r, 16);
+        let end = name.iter().position(|b| *b == 0).unwrap_or(name.len());
+        AdsDeviceInfoRes {
+            result,
+            major,
+            minor,
+            build,
+            name: String::from_utf8_lossy(&name[..end]).trim().to_string(),
+        }
+    }
+
+    fn gen_res(&self) -> Self::Result {
+        unreachable!()
+    }
+}
+
+impl IntoBuf for AdsDeviceInfoRes {
+    type Buf = io::Cursor<Vec<u8>>;
+
+    fn into_buf(self) -> Self::Buf {
+        let mut v = Vec::with_capacity(24);
+        let _ = v.write_u32::<LittleEndian>(self.result);
+        v.push(self.major);
+        v.push(self.minor);
+        let _ = v.write_u16::<LittleEndian>(self.build);
+        let mut name = self.name.into_bytes();
+        name.resize(16, 0);
+        v.extend(name);
+        io::Cursor::new(v)
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct AmsTcpHeader<T>
 where
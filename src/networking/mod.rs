@@ -4,9 +4,10 @@ mod client;
 mod codec;
 
 pub use self::client::AdsClient as Client;
-use std::process;
 
-use futures::{future, Future};
+use futures::future::{self, Either};
+use futures::Future;
+use std::fmt;
 use std::net::ToSocketAddrs;
 use tokio_codec::FramedRead;
 use tokio_io::AsyncRead;
@@ -16,56 +17,84 @@ pub use self::client::*;
 pub use self::codec::types::*;
 pub use self::codec::*;
 
+// None if the net id is no AMS NetId
 pub trait ToPlcConn {
-    fn as_plc_conn(&self) -> [u8; 8];
+    fn as_plc_conn(&self) -> Option<[u8; 8]>;
+}
+
+// six numbers from 0 to 255 separated by dots, like 172.16.21.1.1.1
+pub fn parse_net_id(net_id: &str) -> Option<[u8; 6]> {
+    let parts: Vec<u8> = net_id
+        .trim()
+        .split('.')
+        .map(|x| x.parse().ok())
+        .collect::<Option<_>>()?;
+    if parts.len() != 6 {
+        return None;
+    }
+    let mut id = [0u8; 6];
+    id.clone_from_slice(&parts);
+    Some(id)
 }
 
 impl<T> ToPlcConn for (T, u16)
 where
     T: AsRef<str>,
 {
-    fn as_plc_conn(&self) -> [u8; 8] {
-        let net_id: Vec<_> = self
-            .0
-            .as_ref()
-            .split('.')
-            .map(|x| u8::from_str_radix(x, 10).unwrap())
-            .collect();
+    fn as_plc_conn(&self) -> Option<[u8; 8]> {
+        let net_id = parse_net_id(self.0.as_ref())?;
         let mut d = [0u8; 8];
-        d[..6].clone_from_slice(&net_id[..6]);
+        d[..6].clone_from_slice(&net_id);
         d[7] = ((self.1 >> 8) & 0xff) as u8;
         d[6] = (self.1 & 0xff) as u8;
-        d
+        Some(d)
     }
 }
 
 impl ToPlcConn for [u8; 8] {
-    fn as_plc_conn(&self) -> [u8; 8] {
-        *self
+    fn as_plc_conn(&self) -> Option<[u8; 8]> {
+        Some(*self)
     }
 }
 
-pub fn create_client<T: ToSocketAddrs>(
+// a net id that does not parse or an address that does not resolve fails like a refused
+// connection
+pub fn create_client<T: ToSocketAddrs + fmt::Debug>(
     addr: T,
     target: &impl ToPlcConn,
     source: &impl ToPlcConn,
 ) -> impl Future<Item = Addr<Client>, Error = ()> {
-    let target = target.as_plc_conn();
-    let source = source.as_plc_conn();
-    TcpStream::connect(&addr.to_socket_addrs().unwrap().next().unwrap())
-        .and_then(move |stream| {
-            future::ok(Client::create(move |ctx| {
-                let (r, w) = stream.split();
-                ctx.add_stream(FramedRead::new(r, codec::AdsClientCodec));
-                Client::new(
-                    actix::io::FramedWrite::new(w, codec::AdsClientCodec, ctx),
-                    source,
-                    target,
-                )
-            }))
-        })
-        .map_err(|e| {
-            println!("Can not connect to server: {}", e);
-            process::exit(1)
-        })
+    let (target, source) = match (target.as_plc_conn(), source.as_plc_conn()) {
+        (Some(target), Some(source)) => (target, source),
+        _ => {
+            error!("the plc or the source of {:?} has no valid AMS NetId", addr);
+            return Either::B(future::err(()));
+        }
+    };
+    let addr = match addr.to_socket_addrs().map(|mut a| a.next()) {
+        Ok(Some(a)) => a,
+        Ok(None) => {
+            error!("{:?} resolves to no address", addr);
+            return Either::B(future::err(()));
+        }
+        Err(e) => {
+            error!("{:?} does not resolve: {}", addr, e);
+            return Either::B(future::err(()));
+        }
+    };
+    Either::A(
+        TcpStream::connect(&addr)
+            .and_then(move |stream| {
+                future::ok(Client::create(move |ctx| {
+                    let (r, w) = stream.split();
+                    ctx.add_stream(FramedRead::new(r, codec::AdsClientCodec));
+                    Client::new(
+                        actix::io::FramedWrite::new(w, codec::AdsClientCodec, ctx),
+                        source,
+                        target,
+                    )
+                }))
+            })
+            .map_err(move |e| error!("can not connect to {}: {}", addr, e)),
+    )
 }
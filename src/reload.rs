@@ -0,0 +1,532 @@
+use actix::fut::wrap_future;
+use actix::prelude::*;
+use chashmap::CHashMap;
+use futures::future::{self, Either};
+use futures::Future;
+use identify::{identify, Candidate, PlcStatus};
+use networking::{self, parse_net_id, Disconnect, ToPlcConn};
+use serde_json::{self, Value};
+use settings::{self, AmsConn, PlcSetting, Setting, VersionSetting, N};
+use std::fmt;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+use types::{self, AdsVersion};
+use ws::WsState;
+use ws_ads::{AdsMemory, AdsToWsMultiplexer, ClientOf, Reload};
+use xml_to_struct;
+
+const WATCH_INTERVAL: u64 = 2;
+const RETRY_INTERVAL: u64 = 30;
+
+// errors are logged, the version is left out. inconsistent types are only
+// warned about, the affected fields fail when they are decoded
+pub fn read_version(conf: &VersionSetting, cache_dir: &str) -> Option<AdsVersion> {
+    if !Path::new(&conf.path).is_file() {
+        error!("version file {:?} does not exist", conf.path);
+        return None;
+    }
+    let version = if cache_dir.is_empty() {
+        xml_to_struct::read_version(conf)
+    } else {
+        xml_to_struct::read_version_cached(conf, cache_dir)
+    };
+    match version {
+        Ok(v) => {
+            for i in types::check(&v) {
+                warn!("{}: {}", conf.path, i);
+            }
+            Some(v)
+        }
+        Err(errors) => {
+            for e in errors {
+                error!("{}", e);
+            }
+            None
+        }
+    }
+}
+
+// the loaded versions the plc may run, only the configured one if it names a version
+fn candidates(
+    plc: &PlcSetting,
+    loaded: &CHashMap<u32, Arc<AdsVersion>>,
+    versions: &BTreeMap<N, VersionSetting>,
+) -> Result<Vec<Candidate>, String> {
+    let candidates: Vec<Candidate> = versions
+        .iter()
+        .map(|(n, v)| (n.into(), v))
+        .filter(|(n, v): &(u32, &VersionSetting)| match plc.version {
+            Some(version) => *n == version,
+            None => !v.identity.is_empty(),
+        })
+        .filter_map(|(n, v)| {
+            loaded.get(&n).map(|version| Candidate {
+                number: n,
+                version: version.clone(),
+                identity: v.identity.clone(),
+            })
+        })
+        .collect();
+    match plc.version {
+        Some(n) if candidates.is_empty() => Err(format!("version {} is not loaded", n)),
+        None if candidates.is_empty() => Err("no loaded version has an identity".to_string()),
+        _ => Ok(candidates),
+    }
+}
+
+// connects, identifies the running program and serves it. a plc that can not be
+// reached or whose program matches no version is left with that status
+pub fn connect(
+    plc: &PlcSetting,
+    state: Arc<WsState>,
+    versions: &BTreeMap<N, VersionSetting>,
+    source: &AmsConn,
+) -> impl Future<Item = (), Error = ()> {
+    let conn = match plc.as_plc_conn() {
+        Some(conn) => conn,
+        None => {
+            error!("plc {} has no valid AMS NetId", plc.ams_net_id);
+            return Either::B(future::ok(()));
+        }
+    };
+    let range_policy = plc.range_policy;
+    let max_reads = plc.max_reads;
+    let net_id = plc.ams_net_id.clone();
+    let addr = (plc.ip.clone(), plc.tcp_port);
+    let candidates = candidates(plc, &state.versions, versions);
+    state.status.insert(conn, PlcStatus::Identifying);
+    let unreachable = state.clone();
+    let connected = networking::create_client(addr, &conn, source)
+        .then(move |client| match client {
+            Ok(client) => Ok(client),
+            Err(()) => {
+                unreachable.status.insert(conn, PlcStatus::Unreachable);
+                Err(())
+            }
+        })
+        .and_then(move |client| {
+            let identified = match candidates {
+                Ok(candidates) => Either::A(identify(&client, net_id.clone(), candidates)),
+                Err(reason) => Either::B(future::err(reason)),
+            };
+            identified.then(move |identified| {
+                match identified {
+                    Ok((n, version)) => {
+                        info!("plc {} runs version {}", net_id, n);
+                        let mem = AdsMemory::new(&version);
+                        let multiplexer = AdsToWsMultiplexer::new(
+                            client,
+                            mem,
+                            version,
+                            range_policy,
+                            max_reads,
+                        );
+                        state.sender.insert(conn, multiplexer.start());
+                        state.status.insert(conn, PlcStatus::Running(n));
+                    }
+                    Err(reason) => {
+                        error!("plc {} is refused: {}", net_id, reason);
+                        client.do_send(Disconnect);
+                        state.status.insert(conn, PlcStatus::Refused(reason));
+                    }
+                }
+                Ok(())
+            })
+        })
+        .or_else(|_| Ok(()));
+    Either::A(connected)
+}
+
+// identifies a running plc again. it keeps its connection and its clients, the
+// multiplexer only changes its layout if the plc runs another version now
+fn reidentify(
+    plc: &PlcSetting,
+    conn: [u8; 8],
+    state: Arc<WsState>,
+    versions: &BTreeMap<N, VersionSetting>,
+    multiplexer: Addr<AdsToWsMultiplexer>,
+) -> impl Future<Item = (), Error = ()> {
+    let range_policy = plc.range_policy;
+    let max_reads = plc.max_reads;
+    let net_id = plc.ams_net_id.clone();
+    let candidates = candidates(plc, &state.versions, versions);
+    multiplexer.send(ClientOf).map_err(|_| ()).and_then(move |client| {
+        let identified = match candidates {
+            Ok(candidates) => Either::A(identify(&client, net_id.clone(), candidates)),
+            Err(reason) => Either::B(future::err(reason)),
+        };
+        identified.then(move |identified| {
+            match identified {
+                Ok((n, version)) => {
+                    if state.running_version(&conn) != Some(n) {
+                        info!("plc {} runs version {} now", net_id, n);
+                    }
+                    state.status.insert(conn, PlcStatus::Running(n));
+                    multiplexer.do_send(Reload::Version(version, range_policy, max_reads));
+                }
+                Err(reason) => {
+                    error!("plc {} is refused: {}", net_id, reason);
+                    state.sender.remove(&conn);
+                    multiplexer.do_send(Reload::Stop);
+                    client.do_send(Disconnect);
+                    state.status.insert(conn, PlcStatus::Refused(reason));
+                }
+            }
+            Ok(())
+        })
+    })
+}
+
+fn modified(path: &Path) -> Option<SystemTime> {
+    fs::metadata(path).and_then(|m| m.modified()).ok()
+}
+
+// polls the config and version files and applies their changes to the running plcs
+pub struct Watcher {
+    config_file: String,
+    state: Arc<WsState>,
+    setting: Setting,
+    modified: HashMap<PathBuf, Option<SystemTime>>,
+}
+
+impl Watcher {
+    pub fn new(config_file: &str, state: Arc<WsState>, setting: Setting) -> Self {
+        let mut w = Watcher {
+            config_file: config_file.to_string(),
+            state,
+            setting,
+            modified: HashMap::new(),
+        };
+        w.modified = w.stamps();
+        w
+    }
+
+    fn stamps(&self) -> HashMap<PathBuf, Option<SystemTime>> {
+        // a plc file that is added or removed changes the directory
+        settings::config_path(&self.config_file)
+            .into_iter()
+            .chain(Some(PathBuf::from(&self.setting.plc_dir)))
+            .chain(self.setting.plc.iter().filter_map(|p| p.file.clone()))
+            .chain(self.setting.versions.values().map(|v| PathBuf::from(&v.path)))
+            .map(|p| {
+                let m = modified(&p);
+                (p, m)
+            })
+            .collect()
+    }
+
+    fn check(&mut self, ctx: &mut Context<Self>) {
+        let changed: Vec<PathBuf> = self
+            .modified
+            .iter()
+            .filter(|(p, m)| modified(p) != **m)
+            .map(|(p, _)| p.clone())
+            .collect();
+        if changed.is_empty() {
+            return;
+        }
+        info!("{:?} changed, reloading", changed);
+        let setting = match settings::load(&self.config_file) {
+            Ok(s) => s,
+            Err(errors) => {
+                error!("can not reload {}", self.config_file);
+                for e in errors {
+                    error!("{}", e);
+                }
+                self.modified = self.stamps();
+                return;
+            }
+        };
+        let changed: HashSet<u32> = setting
+            .versions
+            .iter()
+            .filter(|(n, v)| {
+                let same = match self.setting.versions.get(*n) {
+                    Some(o) => serde_json::to_value(o).ok() == serde_json::to_value(v).ok(),
+                    None => false,
+                };
+                !same || changed.contains(&PathBuf::from(&v.path))
+            })
+            .map(|(n, _)| n.into())
+            .collect();
+        if setting.bind != self.setting.bind {
+            let bind = &self.setting.bind;
+            warn!("the http server keeps listening on {:?} until a restart", bind);
+        }
+        self.reload_versions(&setting, &changed);
+        self.reload_plcs(&setting, &changed, ctx);
+        *self.state.config.write().unwrap() = setting.plc.clone();
+        self.setting = setting;
+        self.modified = self.stamps();
+    }
+
+    // a version that can not be read keeps running in its last good state
+    fn reload_versions(&self, setting: &Setting, changed: &HashSet<u32>) {
+        for (n, v) in &setting.versions {
+            let n: u32 = n.into();
+            if changed.contains(&n) {
+                if let Some(version) = read_version(v, &setting.cache_dir) {
+                    self.state.versions.insert(n, Arc::new(version));
+                }
+            }
+        }
+        let configured: HashSet<u32> = setting.versions.keys().map(|n| n.into()).collect();
+        self.state.versions.retain(|n, _| configured.contains(n));
+    }
+
+    fn reload_plcs<'a>(
+        &'a self,
+        setting: &'a Setting,
+        changed: &HashSet<u32>,
+        ctx: &mut Context<Self>,
+    ) {
+        let conns = |plcs: &'a [PlcSetting]| -> HashMap<[u8; 8], &'a PlcSetting> {
+            plcs.iter().filter_map(|p| p.as_plc_conn().map(|c| (c, p))).collect()
+        };
+        let old = conns(&self.setting.plc);
+        let new = conns(&setting.plc);
+        for c in old.keys().filter(|c| !new.contains_key(*c)) {
+            self.stop(c);
+        }
+        for (c, plc) in new {
+            let running = self.state.sender.get(&c).map(|a| a.clone());
+            let same = |o: &PlcSetting| {
+                o.ip == plc.ip
+                    && o.tcp_port == plc.tcp_port
+                    && o.version == plc.version
+                    && self.setting.source(o) == setting.source(plc)
+            };
+            let reload = match (old.get(&c), running) {
+                (Some(o), Some(a)) if same(o) => {
+                    let tuned = o.range_policy != plc.range_policy || o.max_reads != plc.max_reads;
+                    match plc.version {
+                        // a configured version is reloaded in place
+                        Some(n) => Some((a, n, tuned || changed.contains(&n))),
+                        // an identified one is identified again whenever a version changed
+                        None if !changed.is_empty() => {
+                            let state = self.state.clone();
+                            let versions = &setting.versions;
+                            ctx.spawn(wrap_future(reidentify(plc, c, state, versions, a)));
+                            continue;
+                        }
+                        None => self.state.running_version(&c).map(|n| (a, n, tuned)),
+                    }
+                }
+                _ => None,
+            };
+            if let Some((a, n, needed)) = reload {
+                if let Some(version) = self.state.versions.get(&n).map(|v| v.clone()) {
+                    if needed {
+                        a.do_send(Reload::Version(version, plc.range_policy, plc.max_reads));
+                    }
+                    continue;
+                }
+            }
+            self.stop(&c);
+            ctx.spawn(wrap_future(connect(
+                plc,
+                self.state.clone(),
+                &setting.versions,
+                setting.source(plc),
+            )));
+        }
+    }
+
+    // an unreachable plc may be back and a refused one may have been given a program
+    // that matches a version since
+    fn retry(&self, ctx: &mut Context<Self>) {
+        for plc in &self.setting.plc {
+            let status = plc.as_plc_conn().and_then(|c| self.state.status.get(&c));
+            if let Some(PlcStatus::Refused(_)) | Some(PlcStatus::Unreachable) =
+                status.map(|s| s.clone())
+            {
+                ctx.spawn(wrap_future(connect(
+                    plc,
+                    self.state.clone(),
+                    &self.setting.versions,
+                    self.setting.source(plc),
+                )));
+            }
+        }
+    }
+
+    fn stop(&self, conn: &[u8; 8]) {
+        self.state.status.remove(conn);
+        if let Some(a) = self.state.sender.remove(conn) {
+            a.do_send(Reload::Stop);
+        }
+    }
+}
+
+impl Actor for Watcher {
+    type Context = Context<Self>;
+
+    fn started(&mut self, ctx: &mut Self::Context) {
+        ctx.run_interval(Duration::new(WATCH_INTERVAL, 0), |act, ctx| act.check(ctx));
+        ctx.run_interval(Duration::new(RETRY_INTERVAL, 0), |act, ctx| act.retry(ctx));
+    }
+}
+
+pub enum PlcChange {
+    List,
+    Add(PlcSetting),
+    // the plc with this net id and port is replaced
+    Update(String, u16, PlcSetting),
+    Remove(String, u16),
+}
+
+// a change from the api, applied like an edit of the config file
+pub struct ManagePlcs {
+    pub token: Option<String>,
+    pub change: PlcChange,
+}
+
+impl Message for ManagePlcs {
+    type Result = Result<Vec<PlcSetting>, ManageError>;
+}
+
+#[derive(Debug)]
+pub enum ManageError {
+    Disabled,
+    Unauthorized,
+    NotFound,
+    Exists,
+    Invalid(Vec<String>),
+    // the plc is listed in the config file
+    Unmanaged(String),
+    Unwritable(String),
+}
+
+impl fmt::Display for ManageError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+        match self {
+            ManageError::Disabled => write!(f, "the plc api is off, it needs an api_token"),
+            ManageError::Unauthorized => write!(f, "the token is missing or wrong"),
+            ManageError::NotFound => write!(f, "the plc is not configured"),
+            ManageError::Exists => write!(f, "a plc with this net id is already configured"),
+            ManageError::Invalid(errors) => write!(f, "{}", errors.join(", ")),
+            ManageError::Unmanaged(e) => write!(f, "{}", e),
+            ManageError::Unwritable(e) => write!(f, "the config can not be written: {}", e),
+        }
+    }
+}
+
+// compares every byte so the time taken does not tell how much of the token was right
+fn same_token(given: &str, token: &str) -> bool {
+    given.len() == token.len()
+        && given
+            .bytes()
+            .zip(token.bytes())
+            .fold(0, |diff, (a, b)| diff | (a ^ b))
+            == 0
+}
+
+fn position(plcs: &[PlcSetting], net_id: &str, port: u16) -> Option<usize> {
+    let net_id = parse_net_id(net_id)?;
+    plcs.iter()
+        .position(|p| parse_net_id(&p.ams_net_id) == Some(net_id) && p.ams_port == port)
+}
+
+fn plc_values<'a>(plcs: impl Iterator<Item = &'a PlcSetting>) -> Vec<Value> {
+    plcs.map(|p| serde_json::to_value(p).unwrap_or(Value::Null))
+        .collect()
+}
+
+impl Watcher {
+    // the config file is the operator's and never written, the api only changes the plcs
+    // of plc_dir
+    fn unmanaged(&self, setting: &Setting) -> Option<String> {
+        let main = plc_values(setting.plc.iter().filter(|p| p.file.is_none()));
+        if main == plc_values(self.setting.plc.iter().filter(|p| p.file.is_none())) {
+            return None;
+        }
+        Some(format!("the plc is listed in {}, edit it there", self.config_file))
+    }
+
+    // plcs of plc_dir are written to their own files
+    fn persist(&self, setting: &Setting) -> Result<(), String> {
+        let old: HashMap<&PathBuf, &PlcSetting> = self
+            .setting
+            .plc
+            .iter()
+            .filter_map(|p| p.file.as_ref().map(|f| (f, p)))
+            .collect();
+        for plc in &setting.plc {
+            if let Some(ref file) = plc.file {
+                let value = serde_json::to_value(plc).map_err(|e| e.to_string())?;
+                let same = old.get(file).map_or(false, |o| {
+                    serde_json::to_value(o).ok().as_ref() == Some(&value)
+                });
+                if !same {
+                    fs::create_dir_all(&setting.plc_dir)
+                        .map_err(|e| format!("{}: {}", setting.plc_dir, e))?;
+                    let text = serde_json::to_string_pretty(&value).map_err(|e| e.to_string())?;
+                    fs::write(file, text).map_err(|e| format!("{}: {}", file.display(), e))?;
+                }
+            }
+        }
+        for file in old.keys() {
+            if !setting.plc.iter().any(|p| p.file.as_ref() == Some(*file)) {
+                fs::remove_file(file).map_err(|e| format!("{}: {}", file.display(), e))?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Handler<ManagePlcs> for Watcher {
+    type Result = Result<Vec<PlcSetting>, ManageError>;
+
+    fn handle(&mut self, m: ManagePlcs, ctx: &mut Self::Context) -> Self::Result {
+        match self.setting.api_token {
+            Some(ref token) if !token.is_empty() => {
+                if !m.token.map_or(false, |t| same_token(&t, token)) {
+                    return Err(ManageError::Unauthorized);
+                }
+            }
+            _ => return Err(ManageError::Disabled),
+        }
+        let mut setting = self.setting.clone();
+        match m.change {
+            PlcChange::List => return Ok(setting.plc),
+            PlcChange::Add(plc) => {
+                let net_id = parse_net_id(&plc.ams_net_id);
+                if setting.plc.iter().any(|p| parse_net_id(&p.ams_net_id) == net_id) {
+                    return Err(ManageError::Exists);
+                }
+                let mut plc = plc;
+                let file = format!("{}.json", plc.ams_net_id.trim());
+                plc.file = Some(Path::new(&setting.plc_dir).join(file));
+                setting.plc.push(plc);
+            }
+            PlcChange::Update(net_id, port, plc) => {
+                let i = position(&setting.plc, &net_id, port).ok_or(ManageError::NotFound)?;
+                let mut plc = plc;
+                plc.file = setting.plc[i].file.clone();
+                setting.plc[i] = plc;
+            }
+            PlcChange::Remove(net_id, port) => {
+                let i = position(&setting.plc, &net_id, port).ok_or(ManageError::NotFound)?;
+                setting.plc.remove(i);
+            }
+        }
+        let errors = settings::validate(&setting);
+        if !errors.is_empty() {
+            return Err(ManageError::Invalid(
+                errors.iter().map(|e| e.to_string()).collect(),
+            ));
+        }
+        if let Some(reason) = self.unmanaged(&setting) {
+            return Err(ManageError::Unmanaged(reason));
+        }
+        self.persist(&setting).map_err(ManageError::Unwritable)?;
+        self.reload_plcs(&setting, &HashSet::new(), ctx);
+        *self.state.config.write().unwrap() = setting.plc.clone();
+        self.setting = setting;
+        self.modified = self.stamps();
+        Ok(self.setting.plc.clone())
+    }
+}
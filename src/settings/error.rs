@@ -0,0 +1,106 @@
+use std::fmt;
+
+#[derive(Debug)]
+pub enum ConfigError {
+    // a root names neither a struct type nor a symbol or program of the version
+    MissingRoot { version: String, root: String },
+    MissingField {
+        version: String,
+        root: String,
+        field: String,
+    },
+    // the heartbeat counts, so its field has to be an integer
+    NoCounter {
+        version: String,
+        root: String,
+        field: String,
+    },
+    InvalidPattern {
+        version: String,
+        pattern: String,
+        error: String,
+    },
+    // the identity symbol is no symbol or field of one
+    MissingSymbol { version: String, symbol: String },
+    // a poll pattern that names no root with a symbol
+    NothingToPoll { version: String, pattern: String },
+    // the config file can not be read or does not fit the settings
+    Unreadable { file: String, error: String },
+    InvalidNetId { path: String, net_id: String },
+    InvalidPort { path: String, port: u16 },
+    InvalidAddress { path: String, ip: String },
+    InvalidBind { path: String, bind: String },
+    // plcs are told apart by their net id
+    DuplicatePlc { path: String, first: String },
+    UnknownVersion { path: String, version: u32 },
+    MissingFile { path: String, file: String },
+    // a plc without a version needs a version with an identity
+    Unidentifiable { path: String },
+    // zero where at least one is needed
+    Zero { path: String },
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+        match self {
+            ConfigError::MissingRoot { version, root } => {
+                write!(f, "{}: root {} is neither a type nor a symbol", version, root)
+            }
+            ConfigError::MissingField {
+                version,
+                root,
+                field,
+            } => write!(f, "{}: root {} has no field {}", version, root, field),
+            ConfigError::NoCounter {
+                version,
+                root,
+                field,
+            } => write!(f, "{}: the heartbeat {}.{} is no integer", version, root, field),
+            ConfigError::InvalidPattern {
+                version,
+                pattern,
+                error,
+            } => write!(f, "{}: invalid pattern {}: {}", version, pattern, error),
+            ConfigError::MissingSymbol { version, symbol } => {
+                write!(f, "{}: the identity symbol {} does not exist", version, symbol)
+            }
+            ConfigError::NothingToPoll { version, pattern } => {
+                write!(f, "{}: the poll pattern {} matches no symbol", version, pattern)
+            }
+            ConfigError::Unreadable { file, error } => {
+                write!(f, "{}: can not be read: {}", file, error)
+            }
+            ConfigError::InvalidNetId { path, net_id } => write!(
+                f,
+                "{}: {:?} is no AMS NetId, it needs six numbers from 0 to 255 like 172.16.21.1.1.1",
+                path, net_id
+            ),
+            ConfigError::InvalidPort { path, port } => {
+                write!(f, "{}: {} is no valid port", path, port)
+            }
+            ConfigError::InvalidAddress { path, ip } => {
+                write!(f, "{}: {:?} is neither an ip address nor a host name", path, ip)
+            }
+            ConfigError::InvalidBind { path, bind } => write!(
+                f,
+                "{}: {:?} is no address to listen on like 127.0.0.1:8000",
+                path, bind
+            ),
+            ConfigError::DuplicatePlc { path, first } => {
+                write!(f, "{}: the net id is already used by {}", path, first)
+            }
+            ConfigError::UnknownVersion { path, version } => {
+                write!(f, "{}: version {} is not configured", path, version)
+            }
+            ConfigError::MissingFile { path, file } => {
+                write!(f, "{}: {:?} does not exist", path, file)
+            }
+            ConfigError::Zero { path } => write!(f, "{}: has to be at least 1", path),
+            ConfigError::Unidentifiable { path } => write!(
+                f,
+                "{}: the plc has no version and no version has an identity",
+                path
+            ),
+        }
+    }
+}
@@ -1,46 +1,194 @@
+mod error;
 mod map_deserialize;
-use self::map_deserialize::N;
+mod sources;
+mod validate;
+pub use self::error::ConfigError;
+pub use self::map_deserialize::N;
+pub use self::sources::{config_path, effective, read};
+pub use self::validate::validate;
 use networking::ToPlcConn;
 use std::collections::BTreeMap;
+use std::path::PathBuf;
+use types::{RangePolicy, RootRole};
 
-#[derive(Debug, Deserialize, Serialize)]
+#[derive(Debug, Clone, Deserialize, Serialize)]
 pub struct Setting {
     pub connection_parameter: AmsConn,
     pub plc: Vec<PlcSetting>,
     pub versions: BTreeMap<N, VersionSetting>,
+    // parsed versions are kept here, empty turns the cache off
+    #[serde(default = "default_cache_dir")]
+    pub cache_dir: String,
+    // the http server listens on all of them
+    #[serde(default = "default_bind")]
+    pub bind: Vec<String>,
+    // bearer token of the /plc/ api, the api is off without one
+    #[serde(default)]
+    pub api_token: Option<String>,
+    // a directory with one file per plc, added to the plcs of the config file. the /plc/
+    // api keeps its plcs there and creates it with the first one
+    #[serde(default = "default_plc_dir")]
+    pub plc_dir: String,
 }
 
-#[derive(Debug, Deserialize, Serialize)]
+impl Setting {
+    // the ams address the server uses towards the plc
+    pub fn source<'a>(&'a self, plc: &'a PlcSetting) -> &'a AmsConn {
+        plc.source.as_ref().unwrap_or(&self.connection_parameter)
+    }
+}
+
+// read and validated, every problem is returned at once
+pub fn load(config_file: &str) -> Result<Setting, Vec<ConfigError>> {
+    let setting = read(config_file).map_err(|e| vec![e])?;
+    let errors = validate(&setting);
+    if errors.is_empty() {
+        Ok(setting)
+    } else {
+        Err(errors)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
 pub struct AmsConn {
     pub ams_net_id: String,
     pub ams_port: u16,
 }
 
 impl ToPlcConn for AmsConn {
-    fn as_plc_conn(&self) -> [u8; 8] {
-        let net_id: Vec<_> = self
-            .ams_net_id
-            .split('.')
-            .map(|x| u8::from_str_radix(x, 10).unwrap())
-            .collect();
-        let mut d = [0u8; 8];
-        d[..6].clone_from_slice(&net_id[..6]);
-        d[7] = ((self.ams_port >> 8) & 0xff) as u8;
-        d[6] = (self.ams_port & 0xff) as u8;
-        d
+    fn as_plc_conn(&self) -> Option<[u8; 8]> {
+        (self.ams_net_id.as_str(), self.ams_port).as_plc_conn()
     }
 }
 
-#[derive(Debug, Deserialize, Serialize)]
+#[derive(Debug, Clone, Deserialize, Serialize)]
 pub struct PlcSetting {
-    pub version: u32,
+    // without a version the plc runs whichever version its identity matches
+    #[serde(default)]
+    pub version: Option<u32>,
     pub ip: String,
     pub ams_net_id: String,
     pub ams_port: u16,
+    #[serde(default)]
+    pub range_policy: RangePolicy,
+    // tcp port of the ams router on the plc
+    #[serde(default = "default_tcp_port")]
+    pub tcp_port: u16,
+    // replaces connection_parameter for this plc, like when it is on another network
+    #[serde(default)]
+    pub source: Option<AmsConn>,
+    // reads that may wait for the plc at the same time, the rest is queued
+    #[serde(default = "default_max_reads")]
+    pub max_reads: usize,
+    // the file of plc_dir the plc was read from
+    #[serde(skip)]
+    pub file: Option<PathBuf>,
+}
+
+impl ToPlcConn for PlcSetting {
+    fn as_plc_conn(&self) -> Option<[u8; 8]> {
+        (self.ams_net_id.as_str(), self.ams_port).as_plc_conn()
+    }
 }
 
-#[derive(Debug, Deserialize, Serialize)]
+#[derive(Debug, Clone, Deserialize, Serialize)]
 pub struct VersionSetting {
     pub path: String,
+    #[serde(default)]
     pub symbol_names: Vec<String>,
+    // globs like .Zone* or regexes between slashes like /^\.Zone\d+$/
+    #[serde(default)]
+    pub include: Vec<String>,
+    #[serde(default)]
+    pub exclude: Vec<String>,
+    #[serde(default)]
+    pub all_symbols: bool,
+    // bytes the symbols selected by patterns or all_symbols may take together
+    #[serde(default = "default_size_limit")]
+    pub size_limit: u64,
+    #[serde(default = "default_roots")]
+    pub roots: Vec<RootSetting>,
+    #[serde(default)]
+    pub identity: Identity,
+    // roots and symbols that are read again and again, others only on start and resolve
+    #[serde(default)]
+    pub poll: Vec<PollSetting>,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct PollSetting {
+    // root or symbol names, globs and regexes like include
+    pub symbols: Vec<String>,
+    // milliseconds between two reads
+    pub interval: u64,
+    // up to this many milliseconds are added to every interval, so polls do not line up
+    #[serde(default)]
+    pub jitter: u64,
+}
+
+// how the running program is recognised as this version, every given part has to match
+#[derive(Debug, Clone, Default, PartialEq, Deserialize, Serialize)]
+pub struct Identity {
+    // the name ReadDeviceInfo reports, alone or with its version like "Plc30 App 3.1.4024"
+    pub device: Option<String>,
+    // a symbol or a field of one, like .Slave.udiVersion
+    pub symbol: Option<String>,
+    // the value of the symbol, the version number if not given
+    pub value: Option<String>,
+    // sha1 of the symbol table the plc uploads, as logged when a plc is identified
+    pub symbol_checksum: Option<String>,
+}
+
+impl Identity {
+    pub fn is_empty(&self) -> bool {
+        self.device.is_none() && self.symbol.is_none() && self.symbol_checksum.is_none()
+    }
+}
+
+// a struct type or a symbol that is read and exposed as a whole
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct RootSetting {
+    pub name: String,
+    #[serde(default)]
+    pub role: RootRole,
+    // field of a to_plc root the heartbeat counter is written into
+    #[serde(default)]
+    pub heartbeat: Option<String>,
+}
+
+fn default_cache_dir() -> String {
+    "cache".to_string()
+}
+
+pub fn default_plc_dir() -> String {
+    "plc.d".to_string()
+}
+
+fn default_bind() -> Vec<String> {
+    vec!["127.0.0.1:8000".to_string()]
+}
+
+fn default_tcp_port() -> u16 {
+    48898
+}
+
+fn default_max_reads() -> usize {
+    4
+}
+
+fn default_size_limit() -> u64 {
+    1 << 20
+}
+
+fn default_roots() -> Vec<RootSetting> {
+    let root = |name: &str, role, heartbeat: Option<&str>| RootSetting {
+        name: name.to_string(),
+        role,
+        heartbeat: heartbeat.map(|h| h.to_string()),
+    };
+    vec![
+        root("ST_ADS_FROM_BC", RootRole::FromPlc, None),
+        root("ST_ADS_TO_BC", RootRole::ToPlc, Some("udiRequestCounter")),
+        root("ST_RETAIN_DATA", RootRole::Retain, None),
+    ]
 }
@@ -0,0 +1,304 @@
+use super::{default_plc_dir, ConfigError, Setting};
+use config::{self, Config, File, Source, Value};
+use serde_json;
+use std::collections::{BTreeMap, HashMap};
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+const PREFIX: &str = "APP_";
+const EXTENSIONS: &[&str] = &["json", "hjson", "toml", "yaml", "yml", "ini"];
+// values that are not shown with the effective config
+const SECRETS: &[&str] = &["api_token"];
+
+// APP_PLC__0__AMS_NET_ID is plc[0].ams_net_id, the numbers below versions are keys
+fn env_path(var: &str) -> Option<String> {
+    if !var.starts_with(PREFIX) {
+        return None;
+    }
+    let mut path = String::new();
+    let mut parent = String::new();
+    for part in var[PREFIX.len()..].split("__") {
+        let part = part.to_lowercase();
+        if part.is_empty() {
+            return None;
+        }
+        match part.parse::<usize>() {
+            Ok(i) if !path.is_empty() && parent != "versions" => {
+                path.push_str(&format!("[{}]", i))
+            }
+            _ => {
+                if !path.is_empty() {
+                    path.push('.');
+                }
+                path.push_str(&part);
+            }
+        }
+        parent = part;
+    }
+    Some(path)
+}
+
+// the variable, the path it sets and its value
+fn env_vars() -> Vec<(String, String, String)> {
+    env::vars()
+        .filter_map(|(var, value)| env_path(&var).map(|path| (var, path, value)))
+        .collect()
+}
+
+#[derive(Debug, Clone)]
+struct Env;
+
+impl Source for Env {
+    fn clone_into_box(&self) -> Box<Source + Send + Sync> {
+        Box::new(self.clone())
+    }
+
+    fn collect(&self) -> Result<HashMap<String, Value>, config::ConfigError> {
+        Ok(env_vars()
+            .into_iter()
+            .map(|(var, path, value)| (path, Value::new(Some(&var), value)))
+            .collect())
+    }
+}
+
+// one plc per file, appended to the plcs of the config file
+#[derive(Debug, Clone)]
+struct PlcDir {
+    files: Vec<PathBuf>,
+    first: usize,
+}
+
+impl Source for PlcDir {
+    fn clone_into_box(&self) -> Box<Source + Send + Sync> {
+        Box::new(self.clone())
+    }
+
+    fn collect(&self) -> Result<HashMap<String, Value>, config::ConfigError> {
+        let mut plcs = HashMap::new();
+        for (i, file) in self.files.iter().enumerate() {
+            let mut plc = Config::new();
+            plc.merge(File::from(file.as_path()))?;
+            plcs.insert(format!("plc[{}]", self.first + i), plc.try_into::<Value>()?);
+        }
+        Ok(plcs)
+    }
+}
+
+// the config file is given like config.json but may be config.hjson on disk
+pub fn config_path(name: &str) -> Option<PathBuf> {
+    let mut path = PathBuf::from(name);
+    if path.is_file() {
+        return Some(path);
+    }
+    for ext in EXTENSIONS {
+        path.set_extension(ext);
+        if path.is_file() {
+            return Some(path);
+        }
+    }
+    None
+}
+
+// a plc_dir that does not exist yet has no plcs
+fn plc_files(dir: &str) -> Result<Vec<PathBuf>, ConfigError> {
+    if !Path::new(dir).exists() {
+        return Ok(Vec::new());
+    }
+    let unreadable = |e: ::std::io::Error| ConfigError::Unreadable {
+        file: dir.to_string(),
+        error: e.to_string(),
+    };
+    let mut files = Vec::new();
+    for entry in fs::read_dir(dir).map_err(&unreadable)? {
+        let path = entry.map_err(&unreadable)?.path();
+        let known = path
+            .extension()
+            .and_then(|e| e.to_str())
+            .map_or(false, |e| EXTENSIONS.contains(&e));
+        if path.is_file() && known {
+            files.push(path);
+        }
+    }
+    files.sort();
+    Ok(files)
+}
+
+// the config file, then the plc files of plc_dir, then APP_* variables. without
+// variables the config file has to exist
+fn sources(config_file: &str) -> Result<(Config, Vec<PathBuf>, usize), ConfigError> {
+    let unreadable = |e: config::ConfigError| ConfigError::Unreadable {
+        file: config_file.to_string(),
+        error: e.to_string(),
+    };
+    let vars = env_vars();
+    let mut settings = Config::new();
+    settings
+        .merge(File::with_name(config_file).required(vars.is_empty()))
+        .map_err(&unreadable)?;
+    let first = settings.get_array("plc").map(|p| p.len()).unwrap_or(0);
+    let plc_dir = vars
+        .into_iter()
+        .find(|(_, path, _)| path == "plc_dir")
+        .map(|(_, _, dir)| dir)
+        .or_else(|| settings.get_str("plc_dir").ok())
+        .unwrap_or_else(default_plc_dir);
+    let files = plc_files(&plc_dir)?;
+    settings
+        .merge(PlcDir {
+            files: files.clone(),
+            first,
+        })
+        .and_then(|s| s.merge(Env))
+        .map_err(&unreadable)?;
+    Ok((settings, files, first))
+}
+
+// plcs from plc_dir remember their file, changes to them are written back there
+fn read_sources(config_file: &str) -> Result<(Setting, Vec<PathBuf>, usize), ConfigError> {
+    let (settings, files, first) = sources(config_file)?;
+    let mut setting = settings
+        .try_into::<Setting>()
+        .map_err(|e| ConfigError::Unreadable {
+            file: config_file.to_string(),
+            error: e.to_string(),
+        })?;
+    for (i, file) in files.iter().enumerate() {
+        if let Some(plc) = setting.plc.get_mut(first + i) {
+            plc.file = Some(file.clone());
+        }
+    }
+    Ok((setting, files, first))
+}
+
+pub fn read(config_file: &str) -> Result<Setting, ConfigError> {
+    read_sources(config_file).map(|(setting, _, _)| setting)
+}
+
+// paths like plc[0].ip of every value below v
+fn leaves(path: String, v: &serde_json::Value, out: &mut Vec<(String, serde_json::Value)>) {
+    let child = |key: &str| {
+        if path.is_empty() {
+            key.to_string()
+        } else {
+            format!("{}.{}", path, key)
+        }
+    };
+    match v {
+        serde_json::Value::Object(o) if !o.is_empty() => {
+            for (k, v) in o {
+                leaves(child(k), v, out);
+            }
+        }
+        serde_json::Value::Array(a) if !a.is_empty() => {
+            for (i, v) in a.iter().enumerate() {
+                leaves(format!("{}[{}]", path, i), v, out);
+            }
+        }
+        v => out.push((path, v.clone())),
+    }
+}
+
+fn file_leaves<F: Source + Send + Sync + 'static>(
+    file: F,
+    prefix: String,
+    origin: &str,
+    origins: &mut BTreeMap<String, String>,
+) {
+    let mut c = Config::new();
+    if c.merge(file).is_err() {
+        return;
+    }
+    if let Ok(values) = c.try_into::<serde_json::Value>() {
+        let mut found = Vec::new();
+        leaves(prefix, &values, &mut found);
+        for (path, _) in found {
+            origins.insert(path, origin.to_string());
+        }
+    }
+}
+
+// every value of the merged settings with the file or variable it came from
+pub fn effective(
+    config_file: &str,
+) -> Result<Vec<(String, serde_json::Value, String)>, ConfigError> {
+    let (setting, files, first) = read_sources(config_file)?;
+    let mut origins = BTreeMap::new();
+    if let Some(path) = config_path(config_file) {
+        let origin = path.display().to_string();
+        file_leaves(File::from(path), String::new(), &origin, &mut origins);
+    }
+    for (i, file) in files.iter().enumerate() {
+        let origin = file.display().to_string();
+        let prefix = format!("plc[{}]", first + i);
+        file_leaves(File::from(file.as_path()), prefix, &origin, &mut origins);
+    }
+    for (var, path, _) in env_vars() {
+        origins.insert(path, var);
+    }
+    let mut values = Vec::new();
+    let merged = serde_json::to_value(&setting).unwrap_or(serde_json::Value::Null);
+    leaves(String::new(), &merged, &mut values);
+    Ok(values
+        .into_iter()
+        .map(|(path, value)| {
+            let value = match value {
+                serde_json::Value::String(_) if SECRETS.contains(&path.as_str()) => json!("***"),
+                v => v,
+            };
+            let origin = origins
+                .get(&path)
+                .cloned()
+                .unwrap_or_else(|| "default".to_string());
+            (path, value, origin)
+        })
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::process;
+
+    #[test]
+    fn variables_name_their_paths() {
+        assert_eq!(env_path("APP_PLC__0__AMS_NET_ID"), Some("plc[0].ams_net_id".to_string()));
+        assert_eq!(env_path("APP_VERSIONS__14__PATH"), Some("versions.14.path".to_string()));
+        assert_eq!(env_path("APP_API_TOKEN"), Some("api_token".to_string()));
+        assert_eq!(env_path("APP_PLC____IP"), None);
+        assert_eq!(env_path("PLC__0__IP"), None);
+    }
+
+    #[test]
+    fn plc_files_follow_the_plcs_of_the_config_file() {
+        let dir = env::temp_dir().join(format!("ads_server_plc_dir_{}", process::id()));
+        let plcs = dir.join("plcs");
+        fs::create_dir_all(&plcs).unwrap();
+        let plc = |net_id: &str| json!({"ip": "127.0.0.1", "ams_net_id": net_id, "ams_port": 800});
+        let config = json!({
+            "connection_parameter": {"ams_net_id": "10.0.0.1.1.1", "ams_port": 801},
+            "plc": [plc("10.0.0.2.1.1")],
+            "versions": {},
+            "plc_dir": plcs.to_str().unwrap(),
+            "api_token": "s3cret",
+        });
+        let config_file = dir.join("config.json");
+        fs::write(&config_file, config.to_string()).unwrap();
+        fs::write(plcs.join("b.json"), plc("10.0.0.4.1.1").to_string()).unwrap();
+        fs::write(plcs.join("a.json"), plc("10.0.0.3.1.1").to_string()).unwrap();
+        fs::write(plcs.join("notes.txt"), "").unwrap();
+        let config_file = config_file.to_str().unwrap();
+        let setting = read(config_file).unwrap();
+        let net_ids: Vec<&str> = setting.plc.iter().map(|p| p.ams_net_id.as_str()).collect();
+        assert_eq!(net_ids, vec!["10.0.0.2.1.1", "10.0.0.3.1.1", "10.0.0.4.1.1"]);
+        let files: Vec<Option<PathBuf>> = setting.plc.iter().map(|p| p.file.clone()).collect();
+        assert_eq!(files, vec![None, Some(plcs.join("a.json")), Some(plcs.join("b.json"))]);
+        let values = effective(config_file).unwrap();
+        let value = |path: &str| values.iter().find(|v| v.0 == path).cloned().unwrap();
+        assert_eq!(value("plc[2].ams_net_id").2, plcs.join("b.json").display().to_string());
+        assert_eq!(value("api_token").1, json!("***"));
+        let missing = dir.join("missing");
+        assert!(plc_files(missing.to_str().unwrap()).unwrap().is_empty());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,132 @@
+use super::{AmsConn, ConfigError, Setting};
+use networking::parse_net_id;
+use std::collections::HashMap;
+use std::net::{IpAddr, SocketAddr};
+use std::path::Path;
+
+fn host_name(ip: &str) -> bool {
+    !ip.is_empty()
+        && ip.len() <= 253
+        && ip.split('.').all(|label| {
+            !label.is_empty()
+                && label.len() <= 63
+                && !label.starts_with('-')
+                && !label.ends_with('-')
+                && label.chars().all(|c| c.is_ascii_alphanumeric() || c == '-')
+        })
+}
+
+// an ip address or a host name with a port
+fn bind(bind: &str) -> bool {
+    if bind.parse::<SocketAddr>().is_ok() {
+        return true;
+    }
+    match bind.rfind(':') {
+        Some(i) => host_name(&bind[..i]) && bind[i + 1..].parse::<u16>().is_ok(),
+        None => false,
+    }
+}
+
+fn net_id(path: String, net_id: &str, errors: &mut Vec<ConfigError>) {
+    if parse_net_id(net_id).is_none() {
+        errors.push(ConfigError::InvalidNetId {
+            path,
+            net_id: net_id.to_string(),
+        });
+    }
+}
+
+fn port(path: String, port: u16, errors: &mut Vec<ConfigError>) {
+    if port == 0 {
+        errors.push(ConfigError::InvalidPort { path, port });
+    }
+}
+
+fn source(path: &str, source: &AmsConn, errors: &mut Vec<ConfigError>) {
+    net_id(format!("{}.ams_net_id", path), &source.ams_net_id, errors);
+    port(format!("{}.ams_port", path), source.ams_port, errors);
+}
+
+// everything that would only fail once the server runs, each with its place in the config
+pub fn validate(setting: &Setting) -> Vec<ConfigError> {
+    let mut errors = Vec::new();
+    source("connection_parameter", &setting.connection_parameter, &mut errors);
+    if setting.bind.is_empty() {
+        errors.push(ConfigError::InvalidBind {
+            path: "bind".to_string(),
+            bind: String::new(),
+        });
+    }
+    for (i, b) in setting.bind.iter().enumerate() {
+        if !bind(b) {
+            errors.push(ConfigError::InvalidBind {
+                path: format!("bind[{}]", i),
+                bind: b.clone(),
+            });
+        }
+    }
+
+    let identified = setting.versions.values().any(|v| !v.identity.is_empty());
+    let mut seen: HashMap<String, String> = HashMap::new();
+    for (i, plc) in setting.plc.iter().enumerate() {
+        let plc_path = format!("plc[{}]", i);
+        let at = |field: &str| format!("{}.{}", plc_path, field);
+        net_id(at("ams_net_id"), &plc.ams_net_id, &mut errors);
+        port(at("ams_port"), plc.ams_port, &mut errors);
+        port(at("tcp_port"), plc.tcp_port, &mut errors);
+        if plc.max_reads == 0 {
+            errors.push(ConfigError::Zero {
+                path: at("max_reads"),
+            });
+        }
+        if let Some(ref s) = plc.source {
+            source(&at("source"), s, &mut errors);
+        }
+        if plc.ip.parse::<IpAddr>().is_err() && !host_name(&plc.ip) {
+            errors.push(ConfigError::InvalidAddress {
+                path: at("ip"),
+                ip: plc.ip.clone(),
+            });
+        }
+        let key = parse_net_id(&plc.ams_net_id)
+            .map_or(plc.ams_net_id.trim().to_string(), |id| format!("{:?}", id));
+        match seen.get(&key) {
+            Some(first) => errors.push(ConfigError::DuplicatePlc {
+                path: at("ams_net_id"),
+                first: first.clone(),
+            }),
+            None => {
+                seen.insert(key, plc_path.clone());
+            }
+        }
+        match plc.version {
+            Some(n) if !setting.versions.contains_key(&n.into()) => {
+                errors.push(ConfigError::UnknownVersion {
+                    path: at("version"),
+                    version: n,
+                })
+            }
+            None if !identified => errors.push(ConfigError::Unidentifiable {
+                path: plc_path.clone(),
+            }),
+            _ => (),
+        }
+    }
+
+    for (n, v) in &setting.versions {
+        if !Path::new(&v.path).is_file() {
+            errors.push(ConfigError::MissingFile {
+                path: format!("versions.{}.path", **n),
+                file: v.path.clone(),
+            });
+        }
+        for (i, p) in v.poll.iter().enumerate() {
+            if p.interval == 0 {
+                errors.push(ConfigError::Zero {
+                    path: format!("versions.{}.poll[{}].interval", **n, i),
+                });
+            }
+        }
+    }
+    errors
+}
@@ -0,0 +1,312 @@
+use super::{AdsPlcType, AdsType, AdsVersion};
+use chashmap::CHashMap;
+use std::collections::HashSet;
+use std::fmt;
+
+// a type definition that does not add up, found at path when walking from a root
+#[derive(Debug, Serialize)]
+pub struct Inconsistency {
+    #[serde(rename = "type")]
+    pub ty: String,
+    pub path: String,
+    pub message: String,
+}
+
+impl fmt::Display for Inconsistency {
+    fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+        write!(f, "{} ({}): {}", self.path, self.ty, self.message)
+    }
+}
+
+struct Checker<'a> {
+    map: &'a CHashMap<String, AdsType>,
+    // every type is checked once, at the first path it is met
+    checked: HashSet<String>,
+    found: Vec<Inconsistency>,
+}
+
+impl<'a> Checker<'a> {
+    fn report<T: fmt::Display>(&mut self, ty: T, path: &str, message: String) {
+        self.found.push(Inconsistency {
+            ty: ty.to_string(),
+            path: path.to_string(),
+            message,
+        });
+    }
+
+    // the size in bits, None if the type can not be resolved
+    fn plc_type(&mut self, path: &str, ty: &AdsPlcType) -> Option<u32> {
+        match ty {
+            AdsPlcType::Other {
+                ref name,
+                ref reference,
+            } => {
+                let key = reference.trim().to_string();
+                let resolved = match self.map.get(&key) {
+                    Some(t) => t,
+                    None => {
+                        let message = format!("refers to {}, which is not in the type map", key);
+                        self.report(name.trim(), path, message);
+                        return None;
+                    }
+                };
+                // references are decorations, two types sharing one resolve to the same
+                match *resolved {
+                    AdsType::Struct { name: ref found, .. } | AdsType::Enum { name: ref found, .. }
+                        if found.trim() != name.trim() =>
+                    {
+                        let message = format!("refers to {}, which is {}", key, found);
+                        self.report(name.trim(), path, message);
+                    }
+                    _ => (),
+                }
+                if self.checked.insert(key) {
+                    self.ads_type(path, &resolved);
+                }
+                Some(resolved.len() * 8)
+            }
+            AdsPlcType::Bit => Some(1),
+            AdsPlcType::SInt(Some(r))
+            | AdsPlcType::USInt(Some(r))
+            | AdsPlcType::Int(Some(r))
+            | AdsPlcType::UInt(Some(r))
+            | AdsPlcType::DInt(Some(r))
+            | AdsPlcType::UDInt(Some(r))
+            | AdsPlcType::LInt(Some(r))
+            | AdsPlcType::ULInt(Some(r))
+                if r.min > r.max =>
+            {
+                let message = format!("the subrange {}..{} is empty", r.min, r.max);
+                self.report(ty, path, message);
+                Some(AdsType::Primitive(ty.clone()).len() * 8)
+            }
+            ty => Some(AdsType::Primitive(ty.clone()).len() * 8),
+        }
+    }
+
+    fn ads_type(&mut self, path: &str, ty: &AdsType) {
+        match ty {
+            AdsType::Struct {
+                name,
+                bit_size,
+                properties,
+            } => {
+                let mut fields: Vec<(u32, u32, &str)> = Vec::new();
+                for p in properties {
+                    let field = format!("{}.{}", path, p.name.trim());
+                    if let Some(size) = self.plc_type(&field, &p.ty) {
+                        fields.push((p.bit_offs, size, p.name.trim()));
+                    }
+                }
+                fields.sort();
+                let mut end = 0;
+                for (offs, size, field) in &fields {
+                    let at = format!("{}.{}", path, field);
+                    if *offs < end {
+                        let message = format!("starts at bit {}, inside the field before", offs);
+                        self.report(name, &at, message);
+                    }
+                    if offs + size > *bit_size {
+                        let message = format!(
+                            "ends at bit {}, behind the BitSize {} of the struct",
+                            offs + size,
+                            bit_size
+                        );
+                        self.report(name, &at, message);
+                    }
+                    end = end.max(offs + size);
+                }
+                // gaps are padding, only fields taking more than the struct are wrong
+                let sum: u32 = fields.iter().map(|(_, size, _)| size).sum();
+                if sum > *bit_size {
+                    let message =
+                        format!("the fields take {} bits, the BitSize is {}", sum, bit_size);
+                    self.report(name, path, message);
+                }
+            }
+            AdsType::Array {
+                dimensions,
+                bit_size,
+                ty: element,
+            } => {
+                let count = dimensions.iter().fold(1, |n, d| n * d.elements as u32);
+                if count == 0 {
+                    self.report(ty, path, "has no elements".to_string());
+                }
+                if let Some(size) = self.plc_type(&format!("{}[]", path), element) {
+                    if count * size != *bit_size {
+                        let message = format!(
+                            "{} elements of {} bits take {} bits, the BitSize is {}",
+                            count,
+                            size,
+                            count * size,
+                            bit_size
+                        );
+                        self.report(ty, path, message);
+                    }
+                }
+            }
+            AdsType::Enum {
+                name,
+                bit_size,
+                ty: base,
+                keys,
+            } => {
+                if keys.is_empty() {
+                    self.report(name, path, "has no values".to_string());
+                }
+                if let Some(size) = self.plc_type(path, base) {
+                    if size != *bit_size {
+                        let message = format!(
+                            "the base type {} has {} bits, the BitSize is {}",
+                            base, size, bit_size
+                        );
+                        self.report(name, path, message);
+                    }
+                }
+            }
+            AdsType::Primitive(ref p) => {
+                self.plc_type(path, p);
+            }
+        }
+    }
+}
+
+// walks every root and symbol of the version and collects everything that
+// would otherwise only fail while data is decoded
+pub fn check(version: &AdsVersion) -> Vec<Inconsistency> {
+    let mut c = Checker {
+        map: &version.map,
+        checked: HashSet::new(),
+        found: Vec::new(),
+    };
+    let mut roots: Vec<(String, String)> = version.search_index.clone().into_iter().collect();
+    roots.sort();
+    for (root, key) in roots {
+        match version.map.get(&key) {
+            Some(ty) => {
+                if c.checked.insert(key) {
+                    c.ads_type(&root, &ty);
+                }
+            }
+            None => {
+                let message = format!("the root refers to {}, which is not in the type map", key);
+                c.report(&key, &root, message);
+            }
+        }
+    }
+    let mut symbols: Vec<(String, AdsPlcType)> = version
+        .symbols
+        .clone()
+        .into_iter()
+        .map(|(name, s)| (name, s.ty))
+        .collect();
+    symbols.sort_by(|a, b| a.0.cmp(&b.0));
+    for (name, ty) in symbols {
+        c.plc_type(&name, &ty);
+    }
+    c.found
+}
+
+#[cfg(test)]
+mod tests {
+    use types::testing::{other, prop, version};
+    use types::{AdsStructProperties, ArrayDimension, RootRole, SubRange};
+    use super::*;
+
+    fn findings(version: &AdsVersion) -> Vec<(String, String)> {
+        check(version)
+            .into_iter()
+            .map(|i| (i.path, i.message))
+            .collect()
+    }
+
+    fn root(name: &str, bit_size: u32, properties: Vec<AdsStructProperties>) -> AdsType {
+        AdsType::Struct {
+            name: name.to_string(),
+            bit_size,
+            properties,
+        }
+    }
+
+    #[test]
+    fn consistent_types_have_no_findings() {
+        let ty = root(
+            "ROOT",
+            32,
+            vec![
+                prop("a", 0, AdsPlcType::Int(None)),
+                prop("b", 16, AdsPlcType::Int(None)),
+            ],
+        );
+        let v = version(vec![("ROOT", ty)], &[("ROOT", RootRole::Data)]);
+        assert!(check(&v).is_empty());
+    }
+
+    #[test]
+    fn struct_fields_must_fit() {
+        let ty = root(
+            "ROOT",
+            64,
+            vec![
+                prop("a", 0, AdsPlcType::Int(None)),
+                prop("b", 8, AdsPlcType::Int(None)),
+                prop("c", 24, other("MISSING")),
+                prop("d", 56, AdsPlcType::Int(None)),
+            ],
+        );
+        let v = version(vec![("ROOT", ty)], &[("ROOT", RootRole::Data)]);
+        let expected = vec![
+            (
+                "ROOT.c".to_string(),
+                "refers to MISSING, which is not in the type map".to_string(),
+            ),
+            (
+                "ROOT.b".to_string(),
+                "starts at bit 8, inside the field before".to_string(),
+            ),
+            (
+                "ROOT.d".to_string(),
+                "ends at bit 72, behind the BitSize 64 of the struct".to_string(),
+            ),
+        ];
+        assert_eq!(findings(&v), expected);
+    }
+
+    #[test]
+    fn arrays_enums_and_subranges_must_add_up() {
+        let array = AdsType::Array {
+            dimensions: vec![ArrayDimension {
+                lower_bound: 0,
+                elements: 3,
+            }],
+            bit_size: 32,
+            ty: AdsPlcType::Int(None),
+        };
+        let empty = AdsType::Enum {
+            name: "E_EMPTY".to_string(),
+            bit_size: 16,
+            ty: AdsPlcType::Int(None),
+            keys: Vec::new(),
+        };
+        let range = AdsPlcType::Int(Some(SubRange { min: 5, max: 1 }));
+        let ranged = root("RANGE", 16, vec![prop("n", 0, range)]);
+        let v = version(
+            vec![("ARR", array), ("E_EMPTY", empty), ("RANGE", ranged)],
+            &[
+                ("ARR", RootRole::Data),
+                ("E_EMPTY", RootRole::Data),
+                ("RANGE", RootRole::Data),
+            ],
+        );
+        let expected = vec![
+            (
+                "ARR".to_string(),
+                "3 elements of 16 bits take 48 bits, the BitSize is 32".to_string(),
+            ),
+            ("E_EMPTY".to_string(), "has no values".to_string()),
+            ("RANGE.n".to_string(), "the subrange 5..1 is empty".to_string()),
+        ];
+        assert_eq!(findings(&v), expected);
+    }
+}
@@ -0,0 +1,190 @@
+use super::{RangePolicy, ValueFormat};
+use serde_json::{Map, Value};
+use std::fmt;
+use std::io;
+use std::ops::Range;
+
+#[derive(Debug)]
+pub enum CodecErrorKind {
+    InvalidValue,
+    OutOfRange,
+    Clamped(Value),
+    NotFound,
+    ReadOnly,
+    UnknownType,
+    Io(io::Error),
+}
+
+#[derive(Debug)]
+pub struct CodecError {
+    pub path: String,
+    pub expected: String,
+    pub value: Value,
+    pub kind: CodecErrorKind,
+}
+
+impl CodecError {
+    pub fn invalid_value<T: fmt::Display>(expected: T, value: &Value) -> Self {
+        CodecError {
+            path: String::new(),
+            expected: expected.to_string(),
+            value: value.clone(),
+            kind: CodecErrorKind::InvalidValue,
+        }
+    }
+
+    pub fn out_of_range<T: fmt::Display>(expected: T, value: &Value) -> Self {
+        CodecError {
+            kind: CodecErrorKind::OutOfRange,
+            ..CodecError::invalid_value(expected, value)
+        }
+    }
+
+    // not an error, clamped values are written but reported the same way
+    pub fn clamped<T: fmt::Display>(expected: T, value: &Value, to: Value) -> Self {
+        CodecError {
+            kind: CodecErrorKind::Clamped(to),
+            ..CodecError::invalid_value(expected, value)
+        }
+    }
+
+    pub fn not_found<T: fmt::Display>(expected: T) -> Self {
+        CodecError {
+            kind: CodecErrorKind::NotFound,
+            ..CodecError::invalid_value(expected, &Value::Null)
+        }
+    }
+
+    pub fn read_only<T: fmt::Display>(expected: T, value: &Value) -> Self {
+        CodecError {
+            kind: CodecErrorKind::ReadOnly,
+            ..CodecError::invalid_value(expected, value)
+        }
+    }
+
+    pub fn unknown_type(reference: &str) -> Self {
+        CodecError {
+            path: String::new(),
+            expected: reference.to_string(),
+            value: Value::Null,
+            kind: CodecErrorKind::UnknownType,
+        }
+    }
+
+    pub fn io<T: fmt::Display>(expected: T, e: io::Error) -> Self {
+        CodecError {
+            path: String::new(),
+            expected: expected.to_string(),
+            value: Value::Null,
+            kind: CodecErrorKind::Io(e),
+        }
+    }
+
+    // the path is built from the inside out while the error travels up
+    pub fn field(mut self, name: &str) -> Self {
+        self.prefix_field(name);
+        self
+    }
+
+    pub fn index(mut self, index: &str) -> Self {
+        self.prefix_index(index);
+        self
+    }
+
+    fn prefix_field(&mut self, name: &str) {
+        self.path = if self.path.is_empty() || self.path.starts_with('[') {
+            format!("{}{}", name, self.path)
+        } else {
+            format!("{}.{}", name, self.path)
+        };
+    }
+
+    fn prefix_index(&mut self, index: &str) {
+        self.path = format!("[{}]{}", index, self.path);
+    }
+
+    pub fn to_value(&self) -> Value {
+        let mut m = Map::new();
+        m.insert("path".to_string(), Value::String(self.path.clone()));
+        m.insert("expected".to_string(), Value::String(self.expected.clone()));
+        m.insert("value".to_string(), self.value.clone());
+        m.insert("message".to_string(), Value::String(self.to_string()));
+        Value::Object(m)
+    }
+}
+
+impl fmt::Display for CodecError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+        if !self.path.is_empty() {
+            write!(f, "{}: ", self.path)?;
+        }
+        match self.kind {
+            CodecErrorKind::InvalidValue => {
+                write!(f, "{} is not a valid {}", self.value, self.expected)
+            }
+            CodecErrorKind::OutOfRange => {
+                write!(f, "{} is out of range of {}", self.value, self.expected)
+            }
+            CodecErrorKind::Clamped(ref to) => write!(
+                f,
+                "{} was clamped to {} for {}",
+                self.value, to, self.expected
+            ),
+            CodecErrorKind::NotFound => write!(f, "does not exist in {}", self.expected),
+            CodecErrorKind::ReadOnly => write!(f, "{} can not be written", self.expected),
+            CodecErrorKind::UnknownType => write!(f, "unknown type {}", self.expected),
+            CodecErrorKind::Io(ref e) => write!(f, "can not access {}: {}", self.expected, e),
+        }
+    }
+}
+
+// collects everything that happens while a value is written, so that every
+// field is reported and not only the first one failing
+pub struct WriteContext {
+    pub range: RangePolicy,
+    pub format: ValueFormat,
+    pub clamped: Vec<CodecError>,
+    pub rejected: Vec<CodecError>,
+    pub ranges: Vec<Range<usize>>,
+}
+
+impl WriteContext {
+    pub fn new(range: RangePolicy, format: ValueFormat) -> Self {
+        WriteContext {
+            range,
+            format,
+            clamped: Vec::new(),
+            rejected: Vec::new(),
+            ranges: Vec::new(),
+        }
+    }
+
+    pub fn field<F>(&mut self, name: &str, f: F)
+    where
+        F: FnOnce(&mut WriteContext) -> Result<(), CodecError>,
+    {
+        self.nested(f, |e| e.prefix_field(name))
+    }
+
+    pub fn index<F>(&mut self, index: &str, f: F)
+    where
+        F: FnOnce(&mut WriteContext) -> Result<(), CodecError>,
+    {
+        self.nested(f, |e| e.prefix_index(index))
+    }
+
+    fn nested<F, P>(&mut self, f: F, prefix: P)
+    where
+        F: FnOnce(&mut WriteContext) -> Result<(), CodecError>,
+        P: Fn(&mut CodecError),
+    {
+        let (c, r) = (self.clamped.len(), self.rejected.len());
+        if let Err(e) = f(self) {
+            self.rejected.push(e);
+        }
+        self.clamped[c..]
+            .iter_mut()
+            .chain(self.rejected[r..].iter_mut())
+            .for_each(prefix);
+    }
+}
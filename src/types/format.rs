@@ -0,0 +1,126 @@
+use serde::de::{self, Deserialize, Deserializer};
+use std::collections::HashMap;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum ArrayFormat {
+    Nested,
+    Indexed,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum EnumFormat {
+    Name,
+    NameAndValue,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum TimeFormat {
+    Iso,
+    Raw,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum UnitFormat {
+    Raw,
+    Scaled,
+}
+
+// what happens to written values outside of their subrange or native range
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RangePolicy {
+    Clamp,
+    Reject,
+    PassThrough,
+}
+
+impl Default for RangePolicy {
+    fn default() -> Self {
+        RangePolicy::Clamp
+    }
+}
+
+// the config crate hands unit variants over as plain strings
+impl<'de> Deserialize<'de> for RangePolicy {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let name = String::deserialize(deserializer)?;
+        RangePolicy::from_name(&name).ok_or_else(|| {
+            de::Error::unknown_variant(&name, &["clamp", "reject", "pass_through"])
+        })
+    }
+}
+
+impl RangePolicy {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name.trim() {
+            "clamp" => Some(RangePolicy::Clamp),
+            "reject" => Some(RangePolicy::Reject),
+            "pass_through" => Some(RangePolicy::PassThrough),
+            _ => None,
+        }
+    }
+
+    pub fn from_query(query: &HashMap<String, String>) -> Option<Self> {
+        query.get("range").and_then(|r| {
+            let policy = RangePolicy::from_name(r);
+            if policy.is_none() {
+                warn!("unknown range policy {}", r);
+            }
+            policy
+        })
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub struct ValueFormat {
+    pub arrays: ArrayFormat,
+    pub enums: EnumFormat,
+    pub time: TimeFormat,
+    pub units: UnitFormat,
+}
+
+impl Default for ValueFormat {
+    fn default() -> Self {
+        ValueFormat {
+            arrays: ArrayFormat::Nested,
+            enums: EnumFormat::Name,
+            time: TimeFormat::Iso,
+            units: UnitFormat::Raw,
+        }
+    }
+}
+
+impl ValueFormat {
+    pub fn from_query(query: &HashMap<String, String>) -> Self {
+        let mut format = ValueFormat::default();
+        if let Some(a) = query.get("arrays") {
+            match a.as_ref() {
+                "nested" => format.arrays = ArrayFormat::Nested,
+                "indexed" => format.arrays = ArrayFormat::Indexed,
+                a => warn!("unknown array format {}", a),
+            }
+        }
+        if let Some(e) = query.get("enums") {
+            match e.as_ref() {
+                "name" => format.enums = EnumFormat::Name,
+                "name_and_value" => format.enums = EnumFormat::NameAndValue,
+                e => warn!("unknown enum format {}", e),
+            }
+        }
+        if let Some(t) = query.get("time") {
+            match t.as_ref() {
+                "iso" => format.time = TimeFormat::Iso,
+                "raw" => format.time = TimeFormat::Raw,
+                t => warn!("unknown time format {}", t),
+            }
+        }
+        if let Some(u) = query.get("units") {
+            match u.as_ref() {
+                "raw" => format.units = UnitFormat::Raw,
+                "scaled" => format.units = UnitFormat::Scaled,
+                u => warn!("unknown unit format {}", u),
+            }
+        }
+        format
+    }
+}
@@ -1,4 +1,4 @@
-use super::{AdsPlcType, SubRange};
+use super::{AdsPlcType, CodecError, RangePolicy, SubRange, WriteContext};
 use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
 use num_traits::{Bounded, FromPrimitive, ToPrimitive};
 use serde_json::Value;
@@ -24,6 +24,29 @@ where
     }
 }
 
+// the bit size of a field or symbol is authoritative for strings, STRING(MAX_LEN) has no
+// length of its own
+pub fn fit_string(ty: AdsPlcType, bit_size: u32) -> AdsPlcType {
+    let bytes = bit_size as usize / 8;
+    match ty {
+        AdsPlcType::String(_) if bytes > 0 => AdsPlcType::String(bytes - 1),
+        AdsPlcType::WString(_) if bytes > 1 => AdsPlcType::WString(bytes / 2 - 1),
+        ty => ty,
+    }
+}
+
+// integers may also arrive as strings, 64 bit values are sent that way
+fn integer_from_value(d: &Value) -> Option<i128> {
+    match d {
+        Value::String(ref s) => s.trim().parse().ok(),
+        Value::Number(ref n) => n
+            .as_i64()
+            .map(i128::from)
+            .or_else(|| n.as_u64().map(i128::from)),
+        _ => None,
+    }
+}
+
 pub fn type_from_value(d: &Value, r: Option<SubRange>) -> AdsPlcType {
     (match d {
         Value::String(ref s) => (s.to_string(), None, r),
@@ -46,64 +69,86 @@ pub fn type_from_value(d: &Value, r: Option<SubRange>) -> AdsPlcType {
     }).into()
 }
 
-pub fn write_ads_number<T: Bounded + PartialOrd + ToPrimitive + FromPrimitive, W: WriteBytesExt>(
-    data: T,
+pub fn write_ads_number<T: Bounded + ToPrimitive, W: WriteBytesExt>(
+    ty: &AdsPlcType,
+    data: &Value,
     w: &mut W,
     s: &Option<SubRange>,
-) -> Result<(), std::io::Error> {
-    let sr = match s {
-        Some(sr) => (T::from_i64(sr.min).unwrap(), T::from_i64(sr.max).unwrap()),
-        _ => (T::min_value(), T::max_value()),
-    };
-    let value = if data < sr.0 {
-        sr.0
-    } else if data > sr.1 {
-        sr.1
-    } else {
-        data
+    ctx: &mut WriteContext,
+) -> Result<(), CodecError> {
+    let value = integer_from_value(data).ok_or_else(|| CodecError::invalid_value(ty, data))?;
+    let native = (
+        i128::from(T::min_value().to_i64().unwrap()),
+        i128::from(T::max_value().to_u64().unwrap()),
+    );
+    // values outside the native range can not be passed through
+    let bounds = match (s, ctx.range) {
+        (Some(sr), RangePolicy::Clamp) | (Some(sr), RangePolicy::Reject) => (
+            native.0.max(i128::from(sr.min)),
+            native.1.min(i128::from(sr.max)),
+        ),
+        _ => native,
     };
-    if T::min_value().to_i64().unwrap_or(0) < 0 {
-        w.write_int::<LittleEndian>(value.to_i64().unwrap(), std::mem::size_of::<T>())
-    } else {
-        w.write_uint::<LittleEndian>(value.to_u64().unwrap(), std::mem::size_of::<T>())
+    let clamped = value.max(bounds.0).min(bounds.1);
+    if clamped != value {
+        if ctx.range != RangePolicy::Clamp {
+            return Err(CodecError::out_of_range(ty, data));
+        }
+        let to = if clamped < 0 {
+            Value::from(clamped as i64)
+        } else {
+            Value::from(clamped as u64)
+        };
+        ctx.clamped.push(CodecError::clamped(ty, data, to));
     }
+    let size = std::mem::size_of::<T>();
+    if native.0 < 0 {
+        w.write_int::<LittleEndian>(clamped as i64, size)
+    } else {
+        w.write_uint::<LittleEndian>(clamped as u64, size)
+    }.map_err(|e| CodecError::io(ty, e))
 }
 
-fn read_number<T: Bounded + PartialOrd + ToPrimitive + FromPrimitive, Reader: ReadBytesExt>(
-    r: &mut Reader,
-) -> T {
-    if T::min_value().to_i64().unwrap_or(0) < 0 {
-        T::from_i64(
-            r.read_int::<LittleEndian>(std::mem::size_of::<T>())
-                .unwrap(),
-        ).unwrap()
-    } else {
-        T::from_u64(
-            r.read_uint::<LittleEndian>(std::mem::size_of::<T>())
-                .unwrap(),
-        ).unwrap()
+pub fn write_real<W: WriteBytesExt>(
+    ty: &AdsPlcType,
+    data: &Value,
+    w: &mut W,
+    ctx: &mut WriteContext,
+) -> Result<(), CodecError> {
+    let value = data
+        .as_f64()
+        .ok_or_else(|| CodecError::invalid_value(ty, data))?;
+    let max = f64::from(std::f32::MAX);
+    let clamped = value.max(-max).min(max);
+    if clamped != value {
+        if ctx.range != RangePolicy::Clamp {
+            return Err(CodecError::out_of_range(ty, data));
+        }
+        ctx.clamped
+            .push(CodecError::clamped(ty, data, Value::from(clamped)));
     }
+    w.write_f32::<LittleEndian>(clamped as f32)
+        .map_err(|e| CodecError::io(ty, e))
 }
 
-pub fn read_ads_number<T: Bounded + PartialOrd + ToPrimitive + FromPrimitive, R: ReadBytesExt>(
+// the value is passed on as the plc has it, even if it violates its subrange
+pub fn read_ads_number<T: Bounded + ToPrimitive, R: ReadBytesExt>(
     r: &mut R,
-    s: &Option<SubRange>,
-) -> Value {
-    let data = read_number::<T, R>(r);
-    let sr = match s {
-        Some(sr) => (T::from_i64(sr.min).unwrap(), T::from_i64(sr.max).unwrap()),
-        _ => (T::min_value(), T::max_value()),
-    };
-    let value = if data < sr.0 {
-        sr.0
-    } else if data > sr.1 {
-        sr.1
+) -> Result<Value, std::io::Error> {
+    let size = std::mem::size_of::<T>();
+    if T::min_value().to_i64().unwrap() < 0 {
+        r.read_int::<LittleEndian>(size).map(Value::from)
     } else {
-        data
-    };
-    if T::min_value() < T::from_i64(0).unwrap() {
-        Value::Number(value.to_i64().unwrap().into())
-    } else {
-        Value::Number(value.to_u64().unwrap().into())
+        r.read_uint::<LittleEndian>(size).map(Value::from)
     }
 }
+
+// 64 bit values are passed as strings, json numbers are only safe up to 2^53
+pub fn read_wide_number<T: Bounded + ToPrimitive, R: ReadBytesExt>(
+    r: &mut R,
+) -> Result<Value, std::io::Error> {
+    read_ads_number::<T, R>(r).map(|v| match v {
+        Value::Number(n) => Value::String(n.to_string()),
+        v => v,
+    })
+}
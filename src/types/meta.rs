@@ -0,0 +1,253 @@
+use serde_json::{Map, Value};
+use std::collections::BTreeMap;
+
+// what the project file tells about a symbol or field besides its type
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct Metadata {
+    pub comment: Option<String>,
+    pub unit: Option<String>,
+    pub scale: Option<f64>,
+    pub attributes: BTreeMap<String, String>,
+    // the declared initial value, in the same shape as a mutation
+    #[serde(with = "json_text")]
+    pub default: Option<Value>,
+}
+
+// the binary cache can not hold a Value, it keeps its json text
+mod json_text {
+    use serde::de::Error;
+    use serde::{Deserialize, Deserializer, Serialize, Serializer};
+    use serde_json::{self, Value};
+
+    pub fn serialize<S: Serializer>(v: &Option<Value>, s: S) -> Result<S::Ok, S::Error> {
+        v.as_ref().map(|v| v.to_string()).serialize(s)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(d: D) -> Result<Option<Value>, D::Error> {
+        match Option::<String>::deserialize(d)? {
+            Some(text) => serde_json::from_str(&text).map(Some).map_err(D::Error::custom),
+            None => Ok(None),
+        }
+    }
+}
+
+impl Metadata {
+    // reads the Comment and Properties of a SubItem or Symbol
+    pub fn from_value(v: &Value) -> Self {
+        let mut meta = Metadata::default();
+        let comment = match v.get("Comment") {
+            Some(Value::String(ref s)) => s.to_string(),
+            Some(Value::Object(ref o)) => o
+                .get("#text")
+                .and_then(|t| t.as_str())
+                .unwrap_or("")
+                .to_string(),
+            _ => String::new(),
+        };
+        let comment = meta.parse_attributes(&comment);
+        let comment = comment.trim();
+        let properties = match v.get("Properties").map(|p| &p["Property"]) {
+            Some(Value::Array(ref a)) => a.clone(),
+            Some(p @ Value::Object(_)) => vec![p.clone()],
+            _ => Vec::new(),
+        };
+        for p in properties {
+            if let Some(name) = p["Name"].as_str() {
+                let value = match p.get("Value") {
+                    Some(Value::String(ref s)) => s.to_string(),
+                    Some(Value::Number(ref n)) => n.to_string(),
+                    _ => String::new(),
+                };
+                meta.attributes.insert(name.trim().to_string(), value);
+            }
+        }
+        meta.unit = match meta.attributes.get("unit") {
+            Some(u) => Some(u.to_string()),
+            None => unit_from_comment(comment),
+        };
+        meta.scale = match meta.attributes.get("scale") {
+            Some(s) => s.trim().parse().ok(),
+            None => scale_from_comment(comment),
+        };
+        if !comment.is_empty() {
+            meta.comment = Some(comment.to_string());
+        }
+        meta.default = v.get("Default").and_then(default_from_value);
+        meta
+    }
+
+    // {attribute 'name' := 'value'} pragmas are taken out of the comment
+    fn parse_attributes(&mut self, comment: &str) -> String {
+        let mut rest = String::new();
+        let mut s = comment;
+        while let Some(start) = s.find("{attribute") {
+            let end = match s[start..].find('}') {
+                Some(end) => start + end,
+                None => break,
+            };
+            rest += &s[..start];
+            let mut parts = s[start + "{attribute".len()..end].splitn(2, ":=");
+            let name = parts.next().unwrap_or("").trim().trim_matches('\'');
+            let value = parts.next().unwrap_or("").trim().trim_matches('\'');
+            if !name.is_empty() {
+                self.attributes.insert(name.to_string(), value.to_string());
+            }
+            s = &s[end + 1..];
+        }
+        rest + s
+    }
+
+    // adds the known entries to a metadata object
+    pub fn extend(&self, m: &mut Map<String, Value>) {
+        if let Some(ref c) = self.comment {
+            m.insert("comment".to_string(), Value::String(c.to_string()));
+        }
+        if let Some(ref u) = self.unit {
+            m.insert("unit".to_string(), Value::String(u.to_string()));
+        }
+        if let Some(s) = self.scale {
+            m.insert("scale".to_string(), Value::from(s));
+        }
+        if !self.attributes.is_empty() {
+            let attributes = self
+                .attributes
+                .iter()
+                .map(|(k, v)| (k.to_string(), Value::String(v.to_string())))
+                .collect();
+            m.insert("attributes".to_string(), Value::Object(attributes));
+        }
+        if let Some(ref d) = self.default {
+            m.insert("default".to_string(), d.clone());
+        }
+    }
+}
+
+// <Default> holds a single value or SubItems naming the fields and elements it sets
+fn default_from_value(d: &Value) -> Option<Value> {
+    let items = match d.get("SubItem") {
+        Some(Value::Array(ref a)) => a.clone(),
+        Some(i @ Value::Object(_)) => vec![i.clone()],
+        _ => return single_default(d),
+    };
+    let mut default = Value::Object(Map::new());
+    for item in items {
+        if let (Some(name), Some(value)) = (item["Name"].as_str(), single_default(&item)) {
+            insert_path(&mut default, name, value);
+        }
+    }
+    Some(default)
+}
+
+fn single_default(d: &Value) -> Option<Value> {
+    if let Some(s) = d.get("String") {
+        return match s {
+            Value::String(_) => Some(s.clone()),
+            Value::Null => Some(Value::String(String::new())),
+            s => Some(Value::String(s.to_string())),
+        };
+    }
+    match d.get("Value").or_else(|| d.get("EnumText")).or_else(|| d.get("DateTime")) {
+        Some(Value::String(ref s)) if s.eq_ignore_ascii_case("true") => Some(true.into()),
+        Some(Value::String(ref s)) if s.eq_ignore_ascii_case("false") => Some(false.into()),
+        Some(Value::String(ref s)) => Some(Value::String(s.trim().to_string())),
+        // the xml reader turns every number into a float
+        Some(Value::Number(ref n)) => match n.as_f64() {
+            Some(f) if f.fract() == 0.0 && f < 0.0 => Some(Value::from(f as i64)),
+            Some(f) if f.fract() == 0.0 => Some(Value::from(f as u64)),
+            _ => Some(Value::Number(n.clone())),
+        },
+        Some(v @ Value::Bool(_)) => Some(v.clone()),
+        _ => None,
+    }
+}
+
+// sets a value at a path like .stParam.aTable[2].iValue, indices become plc index keys
+pub fn insert_path(root: &mut Value, path: &str, value: Value) {
+    let mut node = root;
+    for (i, segment) in path_segments(path).iter().enumerate() {
+        // a reset of a whole subtree already covers everything below it
+        if node.is_null() && value.is_null() && i > 0 {
+            return;
+        }
+        if !node.is_object() {
+            *node = Value::Object(Map::new());
+        }
+        let next = node
+            .as_object_mut()
+            .unwrap()
+            .entry(segment.to_string())
+            .or_insert_with(|| Value::Object(Map::new()));
+        node = next;
+    }
+    *node = value;
+}
+
+// field names and plc indices of a path, a[1,2] is the same as a[1][2]
+pub fn path_segments(path: &str) -> Vec<&str> {
+    path.split(|c| c == '.' || c == '[' || c == ']' || c == ',')
+        .map(|s| s.trim())
+        .filter(|s| !s.is_empty())
+        .collect()
+}
+
+// the first bracket like [°C] or [mm], index ranges like [1..10] are no unit
+fn unit_from_comment(comment: &str) -> Option<String> {
+    let start = comment.find('[')?;
+    let end = start + comment[start..].find(']')?;
+    let unit = comment[start + 1..end].trim();
+    match unit.chars().next() {
+        Some(c) if !c.is_numeric() && !unit.contains(char::is_whitespace) => {
+            Some(unit.to_string())
+        }
+        _ => None,
+    }
+}
+
+// a word like x0.1 means the raw value has to be multiplied by 0.1
+fn scale_from_comment(comment: &str) -> Option<f64> {
+    comment
+        .split_whitespace()
+        .filter(|w| w.starts_with('x') && w.len() > 1)
+        .filter_map(|w| w[1..].trim_right_matches(|c| c == ',' || c == ';').parse().ok())
+        .find(|s: &f64| *s != 0.0)
+}
+
+// applied to every number of a value, so arrays are scaled elementwise
+pub fn scale(v: Value, factor: f64) -> Value {
+    match v {
+        Value::Number(ref n) => n
+            .as_f64()
+            .map(|n| Value::from(n * factor))
+            .unwrap_or_else(|| v.clone()),
+        Value::Array(a) => Value::Array(a.into_iter().map(|v| scale(v, factor)).collect()),
+        Value::Object(o) => Value::Object(
+            o.into_iter()
+                .map(|(k, v)| (k, scale(v, factor)))
+                .collect(),
+        ),
+        v => v,
+    }
+}
+
+// integer types get the nearest raw value
+pub fn unscale(v: &Value, factor: f64, integer: bool) -> Value {
+    match v {
+        Value::Number(ref n) => match n.as_f64().map(|n| n / factor) {
+            Some(n) if integer && n < 0.0 => Value::from(n.round() as i64),
+            Some(n) if integer => Value::from(n.round() as u64),
+            Some(n) => Value::from(n),
+            None => v.clone(),
+        },
+        Value::Array(ref a) => Value::Array(
+            a.iter()
+                .map(|v| unscale(v, factor, integer))
+                .collect(),
+        ),
+        Value::Object(ref o) => Value::Object(
+            o.iter()
+                .map(|(k, v)| (k.to_string(), unscale(v, factor, integer)))
+                .collect(),
+        ),
+        v => v.clone(),
+    }
+}
@@ -1,35 +1,171 @@
+mod check;
+mod error;
+mod format;
 pub mod helper;
+mod meta;
+#[cfg(test)]
+pub mod testing;
+mod time;
 
-use self::helper::{number_from_value, read_ads_number, type_from_value, write_ads_number};
-use byteorder::{ReadBytesExt, WriteBytesExt};
+use self::helper::{
+    fit_string, number_from_value, read_ads_number, read_wide_number, type_from_value,
+    write_ads_number, write_real,
+};
+use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
 use chashmap::CHashMap;
-use serde_json::Value;
+use serde::de::{self, Deserialize, Deserializer};
+use serde::{Serialize, Serializer};
+use serde_json::{Map, Value};
 use std::collections::HashMap;
-use std::ffi::CString;
-use std::io;
+use std::fmt;
+use std::ops::Range;
+
+pub use self::check::check;
+pub use self::error::{CodecError, WriteContext};
+pub use self::format::{
+    ArrayFormat, EnumFormat, RangePolicy, TimeFormat, UnitFormat, ValueFormat,
+};
+pub use self::meta::{insert_path, path_segments, Metadata};
 
 #[derive(Debug)]
 pub struct AdsVersion {
     pub map: CHashMap<String, AdsType>,
     pub symbols: CHashMap<String, Symbol>,
     pub search_index: CHashMap<String, String>,
+    // roots that are not plain data
+    pub roles: HashMap<String, RootRole>,
+    // root, byte offset and type of the heartbeat counter
+    pub heartbeat: Option<(String, usize, AdsPlcType)>,
+    // what the identity reads and its length in bytes, a field is given as a symbol of its own
+    pub identity_symbol: Option<(Symbol, u32)>,
+    pub polls: Vec<Poll>,
+}
+
+// roots read every interval plus up to jitter milliseconds
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Poll {
+    pub interval: u64,
+    pub jitter: u64,
+    pub roots: Vec<String>,
+}
+
+// what a root is for, the versions of the settings give it per root
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum RootRole {
+    Data,
+    // written by the plc itself
+    FromPlc,
+    // requests to the plc, carries the heartbeat
+    ToPlc,
+    Retain,
+}
+
+impl RootRole {
+    fn name(self) -> &'static str {
+        match self {
+            RootRole::Data => "data",
+            RootRole::FromPlc => "from_plc",
+            RootRole::ToPlc => "to_plc",
+            RootRole::Retain => "retain",
+        }
+    }
+}
+
+// a name both ways, like the settings give it
+impl Serialize for RootRole {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(self.name())
+    }
+}
+
+impl<'de> Deserialize<'de> for RootRole {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let name = String::deserialize(deserializer)?;
+        match name.trim() {
+            "data" => Ok(RootRole::Data),
+            "from_plc" => Ok(RootRole::FromPlc),
+            "to_plc" => Ok(RootRole::ToPlc),
+            "retain" => Ok(RootRole::Retain),
+            _ => Err(de::Error::unknown_variant(
+                &name,
+                &["data", "from_plc", "to_plc", "retain"],
+            )),
+        }
+    }
 }
 
-#[derive(Debug, Clone)]
+impl Default for RootRole {
+    fn default() -> Self {
+        RootRole::Data
+    }
+}
+
+// the shape an AdsVersion is stored in, chashmaps can not be serialized directly
+#[derive(Serialize, Deserialize)]
+struct VersionData {
+    map: HashMap<String, AdsType>,
+    symbols: HashMap<String, Symbol>,
+    search_index: HashMap<String, String>,
+    roles: HashMap<String, RootRole>,
+    heartbeat: Option<(String, usize, AdsPlcType)>,
+    identity_symbol: Option<(Symbol, u32)>,
+    polls: Vec<Poll>,
+}
+
+impl Serialize for AdsVersion {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        VersionData {
+            map: self.map.clone().into_iter().collect(),
+            symbols: self.symbols.clone().into_iter().collect(),
+            search_index: self.search_index.clone().into_iter().collect(),
+            roles: self.roles.clone(),
+            heartbeat: self.heartbeat.clone(),
+            identity_symbol: self.identity_symbol.clone(),
+            polls: self.polls.clone(),
+        }.serialize(serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for AdsVersion {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let v = VersionData::deserialize(deserializer)?;
+        Ok(AdsVersion {
+            map: v.map.into_iter().collect(),
+            symbols: v.symbols.into_iter().collect(),
+            search_index: v.search_index.into_iter().collect(),
+            roles: v.roles,
+            heartbeat: v.heartbeat,
+            identity_symbol: v.identity_symbol,
+            polls: v.polls,
+        })
+    }
+}
+
+impl AdsVersion {
+    pub fn root(&self, role: RootRole) -> Option<&str> {
+        self.roles
+            .iter()
+            .find(|(_, r)| **r == role)
+            .map(|(name, _)| name.as_str())
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct Symbol {
     pub index_group: u32,
     pub index_offset: u32,
     pub name: Name,
     pub ty: AdsPlcType,
+    pub metadata: Metadata,
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct Name {
     pub text: String,
     pub decoration: Option<String>,
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub enum AdsPlcType {
     Bool,
     SInt(Option<SubRange>),
@@ -38,34 +174,51 @@ pub enum AdsPlcType {
     UInt(Option<SubRange>),
     DInt(Option<SubRange>),
     UDInt(Option<SubRange>),
+    LInt(Option<SubRange>),
+    ULInt(Option<SubRange>),
     Real,
     LReal,
     String(usize),
+    WString(usize),
     Time,
+    LTime,
     TOD,
     Date,
     DT,
+    Bit,
+    // only the address is known, it can be read but never written
+    Pointer(usize),
     Other { name: String, reference: String },
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct AdsStructProperties {
     pub name: String,
+    pub bit_offs: u32,
     pub ty: AdsPlcType,
+    pub metadata: Metadata,
 }
 
-#[derive(Debug, Copy, Clone)]
+#[derive(Debug, Copy, Clone, PartialEq, Serialize, Deserialize)]
 pub struct SubRange {
     pub min: i64,
     pub max: i64,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Copy, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ArrayDimension {
+    pub lower_bound: i64,
+    pub elements: usize,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub enum AdsType {
     Enum {
         name: String,
         bit_size: u32,
-        keys: HashMap<i16, String>,
+        ty: AdsPlcType,
+        // in declared order, the first one is the initial value
+        keys: Vec<(i64, String)>,
     },
     Struct {
         name: String,
@@ -73,7 +226,7 @@ pub enum AdsType {
         properties: Vec<AdsStructProperties>,
     },
     Array {
-        bounds: usize,
+        dimensions: Vec<ArrayDimension>,
         bit_size: u32,
         ty: AdsPlcType,
     },
@@ -86,97 +239,239 @@ impl AdsPlcType {
         data: &Value,
         w: &mut W,
         map: &CHashMap<String, AdsType>,
-    ) -> Result<(), io::Error> {
+        ctx: &mut WriteContext,
+    ) -> Result<(), CodecError> {
+        let invalid = || CodecError::invalid_value(self, data);
+        let io = |e| CodecError::io(self, e);
         match self {
-            AdsPlcType::Bool => w.write_u8(data.as_bool().expect("no bool") as u8),
-            AdsPlcType::SInt(ref s) => {
-                write_ads_number::<i8, W>(data.as_i64().expect("no sint") as i8, w, s)
-            }
-            AdsPlcType::Int(ref s) => {
-                write_ads_number::<i16, W>(data.as_i64().expect("no int") as i16, w, s)
+            AdsPlcType::Bool => w
+                .write_u8(data.as_bool().ok_or_else(invalid)? as u8)
+                .map_err(io),
+            AdsPlcType::SInt(ref s) => write_ads_number::<i8, W>(self, data, w, s, ctx),
+            AdsPlcType::Int(ref s) => write_ads_number::<i16, W>(self, data, w, s, ctx),
+            AdsPlcType::DInt(ref s) => write_ads_number::<i32, W>(self, data, w, s, ctx),
+            AdsPlcType::USInt(ref s) => write_ads_number::<u8, W>(self, data, w, s, ctx),
+            AdsPlcType::UInt(ref s) => write_ads_number::<u16, W>(self, data, w, s, ctx),
+            AdsPlcType::UDInt(ref s) => write_ads_number::<u32, W>(self, data, w, s, ctx),
+            AdsPlcType::LInt(ref s) => write_ads_number::<i64, W>(self, data, w, s, ctx),
+            AdsPlcType::ULInt(ref s) => write_ads_number::<u64, W>(self, data, w, s, ctx),
+            AdsPlcType::Real => write_real(self, data, w, ctx),
+            AdsPlcType::LReal => w
+                .write_f64::<LittleEndian>(data.as_f64().ok_or_else(invalid)?)
+                .map_err(io),
+            AdsPlcType::Date | AdsPlcType::DT | AdsPlcType::TOD | AdsPlcType::Time => {
+                let t = time::from_value(self, data).ok_or_else(invalid)?;
+                // clamping a time to the u32 range would name another instant, so no policy
+                // applies here
+                if t > u64::from(u32::max_value()) {
+                    return Err(CodecError::out_of_range(self, data));
+                }
+                write_ads_number::<u32, W>(self, &t.into(), w, &None, ctx)
             }
-            AdsPlcType::DInt(ref s) => {
-                write_ads_number::<i32, W>(data.as_i64().expect("no dint") as i32, w, s)
+            AdsPlcType::LTime => {
+                let t = time::from_value(self, data).ok_or_else(invalid)?;
+                write_ads_number::<u64, W>(self, &t.into(), w, &None, ctx)
             }
-            AdsPlcType::USInt(ref s) => {
-                write_ads_number::<u8, W>(data.as_u64().expect("no usint") as u8, w, s)
+            AdsPlcType::Bit => w
+                .write_u8(data.as_bool().ok_or_else(invalid)? as u8)
+                .map_err(io),
+            AdsPlcType::Pointer(_) => Err(CodecError::read_only(self, data)),
+            AdsPlcType::String(ref len) => {
+                let strs = data.as_str().ok_or_else(invalid)?.as_bytes();
+                let mut b = vec![0u8; *len + 1];
+                let n = strs.len().min(*len);
+                b[..n].clone_from_slice(&strs[..n]);
+                w.write_all(&b).map_err(io)
             }
-            AdsPlcType::UInt(ref s) => {
-                write_ads_number::<u16, W>(data.as_u64().expect("no uint") as u16, w, s)
+            AdsPlcType::WString(ref len) => {
+                let mut chars: Vec<u16> = data
+                    .as_str()
+                    .ok_or_else(invalid)?
+                    .encode_utf16()
+                    .take(*len)
+                    .collect();
+                chars.resize(*len + 1, 0);
+                chars
+                    .iter()
+                    .fold(Ok(()), |acc, c| match acc {
+                        Ok(_) => w.write_u16::<LittleEndian>(*c),
+                        _ => acc,
+                    })
+                    .map_err(io)
             }
-            AdsPlcType::UDInt(ref s) => {
-                write_ads_number::<u32, W>(data.as_u64().expect("no udint") as u32, w, s)
+            AdsPlcType::Other { ref reference, .. } => {
+                match map.get(&reference.trim().to_string()) {
+                    Some(ty) => ty.to_writer(data, w, map, ctx),
+                    None => Err(CodecError::unknown_type(reference)),
+                }
             }
-            AdsPlcType::Real => {
-                write_ads_number::<f32, W>(data.as_f64().expect("no real") as f32, w, &None)
+        }
+    }
+
+    // the values a counter of this type runs through before it starts over, none if it is no
+    // integer
+    pub fn counter_values(&self, map: &CHashMap<String, AdsType>) -> Option<u128> {
+        match self {
+            AdsPlcType::SInt(_) => Some(1 << 7),
+            AdsPlcType::USInt(_) => Some(1 << 8),
+            AdsPlcType::Int(_) => Some(1 << 15),
+            AdsPlcType::UInt(_) => Some(1 << 16),
+            AdsPlcType::DInt(_) => Some(1 << 31),
+            AdsPlcType::UDInt(_) => Some(1 << 32),
+            AdsPlcType::LInt(_) => Some(1 << 63),
+            AdsPlcType::ULInt(_) => Some(1 << 64),
+            AdsPlcType::Other { ref reference, .. } => {
+                match map.get(&reference.trim().to_string()).as_ref().map(|t| &**t) {
+                    Some(AdsType::Primitive(ty)) => ty.counter_values(map),
+                    _ => None,
+                }
             }
-            AdsPlcType::LReal => {
-                write_ads_number::<f64, W>(data.as_f64().expect("no lreal") as f64, w, &None)
+            _ => None,
+        }
+    }
+
+    // Some(true) for integers, Some(false) for reals, also through arrays
+    fn numeric(&self, map: &CHashMap<String, AdsType>) -> Option<bool> {
+        match self {
+            AdsPlcType::SInt(_)
+            | AdsPlcType::USInt(_)
+            | AdsPlcType::Int(_)
+            | AdsPlcType::UInt(_)
+            | AdsPlcType::DInt(_)
+            | AdsPlcType::UDInt(_)
+            | AdsPlcType::LInt(_)
+            | AdsPlcType::ULInt(_) => Some(true),
+            AdsPlcType::Real | AdsPlcType::LReal => Some(false),
+            AdsPlcType::Other { ref reference, .. } => {
+                match map.get(&reference.trim().to_string()).as_ref().map(|t| &**t) {
+                    Some(AdsType::Array { ty, .. }) => ty.numeric(map),
+                    Some(AdsType::Primitive(ty)) => ty.numeric(map),
+                    _ => None,
+                }
             }
-            AdsPlcType::Date => {
-                write_ads_number::<u32, W>(data.as_u64().expect("no date") as u32, w, &None)
+            _ => None,
+        }
+    }
+
+    pub fn metadata(&self, map: &CHashMap<String, AdsType>) -> Value {
+        match self {
+            AdsPlcType::Other { ref reference, .. } => {
+                match map.get(&reference.trim().to_string()) {
+                    Some(ty) => ty.metadata(map),
+                    None => Value::Null,
+                }
             }
-            AdsPlcType::DT => {
-                write_ads_number::<u32, W>(data.as_u64().expect("no dt") as u32, w, &None)
+            _ => {
+                let mut m = Map::new();
+                m.insert("type".to_string(), Value::String(self.to_string()));
+                Value::Object(m)
             }
-            AdsPlcType::TOD => {
-                write_ads_number::<u32, W>(data.as_u64().expect("no tod") as u32, w, &None)
+        }
+    }
+
+    fn patch_into(
+        &self,
+        patch: &Value,
+        mem: &mut [u8],
+        offset: usize,
+        map: &CHashMap<String, AdsType>,
+        ctx: &mut WriteContext,
+    ) -> Result<(), CodecError> {
+        match self {
+            AdsPlcType::Other { ref reference, .. } => {
+                match map.get(&reference.trim().to_string()) {
+                    Some(ty) => ty.patch_into(patch, mem, offset, map, ctx),
+                    None => Err(CodecError::unknown_type(reference)),
+                }
             }
-            AdsPlcType::Time => {
-                write_ads_number::<u32, W>(data.as_u64().expect("no time") as u32, w, &None)
+            _ if patch.is_null() => {
+                write_default(mem, offset, ctx, &self.default_bytes(map, None)?);
+                Ok(())
             }
-            AdsPlcType::String(ref len) => {
-                let strs = data.as_str().expect("no str").as_bytes();
-                if *len > strs.len() {
-                    let _ = w.write_all(&strs[..]);
-                    let _ = w.write_all(&vec![0u8; *len - strs.len()]);
-                //println!("{:?}", w);
-                } else {
-                    let _ = w.write_all(&strs[..*len]);
+            _ => write_range(mem, offset, ctx, |w, ctx| self.to_writer(patch, w, map, ctx)),
+        }
+    }
+
+    pub fn default_bytes(
+        &self,
+        map: &CHashMap<String, AdsType>,
+        default: Option<&Value>,
+    ) -> Result<Vec<u8>, CodecError> {
+        match self {
+            AdsPlcType::Other { ref reference, .. } => {
+                match map.get(&reference.trim().to_string()) {
+                    Some(ty) => ty.default_bytes(map, default),
+                    None => Err(CodecError::unknown_type(reference)),
                 }
-                w.write_u8(0)
             }
-            AdsPlcType::Other { ref reference, .. } => map
-                .get(&reference.trim().to_string())
-                .unwrap()
-                .to_writer(data, w, map),
+            ty => AdsType::Primitive(ty.clone()).default_bytes(map, default),
         }
     }
+
     pub fn as_data_struct<R: ReadBytesExt>(
         &self,
         r: &mut R,
         map: &CHashMap<String, AdsType>,
-    ) -> Value {
+        format: &ValueFormat,
+    ) -> Result<Value, CodecError> {
         match self {
-            AdsPlcType::Bool => (r.read_u8().unwrap() >= 1).into(),
-            AdsPlcType::SInt(ref s) => read_ads_number::<i8, R>(r, s),
-            AdsPlcType::Int(ref s) => read_ads_number::<i16, R>(r, s),
-            AdsPlcType::DInt(ref s) => read_ads_number::<i32, R>(r, s),
-            AdsPlcType::USInt(ref s) => read_ads_number::<u8, R>(r, s),
-            AdsPlcType::UInt(ref s) => read_ads_number::<u16, R>(r, s),
-            AdsPlcType::UDInt(ref s) => read_ads_number::<u32, R>(r, s),
-            AdsPlcType::Real => read_ads_number::<f32, R>(r, &None),
-            AdsPlcType::LReal => read_ads_number::<f64, R>(r, &None),
-            AdsPlcType::Date => read_ads_number::<u32, R>(r, &None),
-            AdsPlcType::DT => read_ads_number::<u32, R>(r, &None),
-            AdsPlcType::TOD => read_ads_number::<u32, R>(r, &None),
-            AdsPlcType::Time => read_ads_number::<u32, R>(r, &None),
+            AdsPlcType::Bool => r.read_u8().map(|b| (b >= 1).into()),
+            AdsPlcType::SInt(_) => read_ads_number::<i8, R>(r),
+            AdsPlcType::Int(_) => read_ads_number::<i16, R>(r),
+            AdsPlcType::DInt(_) => read_ads_number::<i32, R>(r),
+            AdsPlcType::USInt(_) => read_ads_number::<u8, R>(r),
+            AdsPlcType::UInt(_) => read_ads_number::<u16, R>(r),
+            AdsPlcType::UDInt(_) => read_ads_number::<u32, R>(r),
+            AdsPlcType::LInt(_) => read_wide_number::<i64, R>(r),
+            AdsPlcType::ULInt(_) => read_wide_number::<u64, R>(r),
+            AdsPlcType::Real => r.read_f32::<LittleEndian>().map(|f| f64::from(f).into()),
+            AdsPlcType::LReal => r.read_f64::<LittleEndian>().map(Value::from),
+            AdsPlcType::Date | AdsPlcType::DT | AdsPlcType::TOD | AdsPlcType::Time => {
+                match format.time {
+                    TimeFormat::Raw => read_ads_number::<u32, R>(r),
+                    TimeFormat::Iso => r
+                        .read_u32::<LittleEndian>()
+                        .map(|raw| time::to_iso(self, u64::from(raw))),
+                }
+            }
+            AdsPlcType::LTime => match format.time {
+                TimeFormat::Raw => read_wide_number::<u64, R>(r),
+                TimeFormat::Iso => r
+                    .read_u64::<LittleEndian>()
+                    .map(|raw| time::to_iso(self, raw)),
+            },
+            AdsPlcType::Bit => r.read_u8().map(|b| (b & 1 == 1).into()),
+            AdsPlcType::Pointer(8) => read_wide_number::<u64, R>(r),
+            AdsPlcType::Pointer(_) => read_ads_number::<u32, R>(r),
             AdsPlcType::String(ref len) => {
                 let mut b = vec![0u8; *len + 1];
-                let _ = r.read_exact(&mut b);
+                r.read_exact(&mut b).map_err(|e| CodecError::io(self, e))?;
                 let i = b.iter().position(|&x| x == 0).unwrap_or(*len);
-                if i > 0 {
-                    let s = CString::new(&b[..i]).expect("nullerror");
-                    Value::String(s.into_string().expect("invalid utf8"))
-                } else {
-                    Value::String("".to_string())
+                return match String::from_utf8(b[..i].to_vec()) {
+                    Ok(s) => Ok(Value::String(s)),
+                    Err(_) => {
+                        let lossy = String::from_utf8_lossy(&b[..i]).to_string();
+                        Err(CodecError::invalid_value(self, &Value::String(lossy)))
+                    }
+                };
+            }
+            AdsPlcType::WString(ref len) => {
+                let mut chars = Vec::with_capacity(*len + 1);
+                for _ in 0..=*len {
+                    chars.push(r
+                        .read_u16::<LittleEndian>()
+                        .map_err(|e| CodecError::io(self, e))?);
                 }
+                let i = chars.iter().position(|&x| x == 0).unwrap_or(*len);
+                Ok(Value::String(String::from_utf16_lossy(&chars[..i])))
             }
-            AdsPlcType::Other { ref reference, .. } => map
-                .get(&reference.trim().to_string())
-                .unwrap()
-                .as_data_struct(r, map),
-        }
+            AdsPlcType::Other { ref reference, .. } => {
+                return match map.get(&reference.trim().to_string()) {
+                    Some(ty) => ty.as_data_struct(r, map, format),
+                    None => Err(CodecError::unknown_type(reference)),
+                }
+            }
+        }.map_err(|e| CodecError::io(self, e))
     }
 }
 
@@ -187,52 +482,239 @@ impl AdsType {
         data: &Value,
         w: &mut W,
         map: &CHashMap<String, AdsType>,
-    ) -> Result<(), io::Error> {
+        ctx: &mut WriteContext,
+    ) -> Result<(), CodecError> {
         match self {
-            AdsType::Enum { keys, .. } => {
-                let mut i = data.as_i64().expect("no enum") as i16;
-                if !keys.contains_key(&i) {
-                    let mut ik = keys.iter();
-                    let (first, _) = ik.next().unwrap();
-                    let (last, _) = ik.last().unwrap();
-                    if i < *first {
-                        i = *first;
-                    } else if i > *last {
-                        i = *last;
+            AdsType::Enum { keys, ty, .. } => {
+                let value = match data {
+                    Value::Object(ref o) if o.contains_key("name") => &o["name"],
+                    Value::Object(ref o) => &o["value"],
+                    _ => data,
+                };
+                let key = match value {
+                    Value::String(ref s) => keys
+                        .iter()
+                        .find(|(_, text)| text.as_str() == s.trim())
+                        .map(|(k, _)| *k),
+                    _ => value.as_i64().filter(|k| keys.iter().any(|(key, _)| key == k)),
+                };
+                match key {
+                    Some(k) => ty.to_writer(&k.into(), w, map, ctx),
+                    None => Err(CodecError::invalid_value(self, value)),
+                }
+            }
+            AdsType::Struct { properties, .. } => {
+                let mut b = vec![0u8; self.len() as usize];
+                for p in properties {
+                    ctx.field(p.name.trim(), |ctx| {
+                        p.to_writer(&data[&p.name], &mut b, map, ctx)
+                    });
+                }
+                w.write_all(&b).map_err(|e| CodecError::io(self, e))
+            }
+            AdsType::Array { ty, dimensions, .. } => {
+                write_array(ty, dimensions, data, w, map, ctx)
+            }
+            AdsType::Primitive(ref ty) => ty.to_writer(data, w, map, ctx),
+        }
+    }
+
+    // writes only the fields present in the patch into mem and returns the
+    // touched byte ranges, adjacent ones merged so they can be written at once
+    pub fn patch(
+        &self,
+        patch: &Value,
+        mem: &mut [u8],
+        map: &CHashMap<String, AdsType>,
+        range: RangePolicy,
+        format: ValueFormat,
+    ) -> Result<(Vec<Range<usize>>, Vec<CodecError>), Vec<CodecError>> {
+        let mut ctx = WriteContext::new(range, format);
+        let mut b = mem.to_vec();
+        if let Err(e) = self.patch_into(patch, &mut b, 0, map, &mut ctx) {
+            ctx.rejected.push(e);
+        }
+        if !ctx.rejected.is_empty() {
+            return Err(ctx.rejected);
+        }
+        mem.clone_from_slice(&b);
+        ctx.ranges.sort_by_key(|r| r.start);
+        let mut ranges: Vec<Range<usize>> = Vec::new();
+        for r in ctx.ranges {
+            match ranges.last_mut() {
+                Some(ref mut last) if r.start <= last.end => last.end = last.end.max(r.end),
+                _ => ranges.push(r),
+            }
+        }
+        Ok((ranges, ctx.clamped))
+    }
+
+    // puts the whole value back to its initial value, default overrides the
+    // one of the type like the initial value of a symbol does
+    pub fn reset(
+        &self,
+        mem: &mut [u8],
+        map: &CHashMap<String, AdsType>,
+        default: Option<&Value>,
+    ) -> Result<Vec<Range<usize>>, CodecError> {
+        let b = self.default_bytes(map, default)?;
+        mem[..b.len()].clone_from_slice(&b);
+        Ok(vec![0..b.len()])
+    }
+
+    // zero, the first enum value or the lower bound of a subrange, unless the
+    // project file declares an initial value
+    pub fn default_bytes(
+        &self,
+        map: &CHashMap<String, AdsType>,
+        default: Option<&Value>,
+    ) -> Result<Vec<u8>, CodecError> {
+        let mut b = vec![0u8; self.len() as usize];
+        let mut ctx = WriteContext::new(RangePolicy::Clamp, ValueFormat::default());
+        match self {
+            AdsType::Enum { keys, ty, .. } => {
+                if let Some((k, _)) = keys.first() {
+                    ty.to_writer(&(*k).into(), &mut &mut b[..], map, &mut ctx)?;
+                }
+            }
+            AdsType::Struct { properties, .. } => {
+                for p in properties {
+                    p.write_default(&mut b, map)
+                        .map_err(|e| e.field(p.name.trim()))?;
+                }
+            }
+            AdsType::Array { ty, .. } => {
+                let element = ty.default_bytes(map, None)?;
+                if !element.is_empty() {
+                    for chunk in b.chunks_mut(element.len()) {
+                        chunk.clone_from_slice(&element[..chunk.len()]);
                     }
                 }
-                write_ads_number(i, w, &None)
+            }
+            AdsType::Primitive(ref ty) => {
+                if ty.numeric(map) == Some(true) {
+                    ty.to_writer(&0.into(), &mut &mut b[..], map, &mut ctx)?;
+                }
+            }
+        }
+        if let Some(d) = default {
+            self.patch_into(d, &mut b, 0, map, &mut ctx)?;
+            if let Some(e) = ctx.rejected.pop() {
+                return Err(e);
+            }
+        }
+        Ok(b)
+    }
+
+    // mem starts at this value, offset is its position in the whole symbol,
+    // null puts it back to its initial value
+    fn patch_into(
+        &self,
+        patch: &Value,
+        mem: &mut [u8],
+        offset: usize,
+        map: &CHashMap<String, AdsType>,
+        ctx: &mut WriteContext,
+    ) -> Result<(), CodecError> {
+        match self {
+            _ if patch.is_null() => {
+                write_default(mem, offset, ctx, &self.default_bytes(map, None)?);
+                Ok(())
             }
             AdsType::Struct { properties, .. } => {
-                properties.iter().fold(Ok(()), |acc, p| match acc {
-                    Ok(_) => p.ty.to_writer(&data[&p.name], w, map),
-                    _ => acc,
-                })
+                let fields = patch
+                    .as_object()
+                    .ok_or_else(|| CodecError::invalid_value(self, patch))?;
+                for (k, v) in fields {
+                    let name = k.trim();
+                    match properties.iter().find(|p| p.name.trim() == name) {
+                        Some(p) => ctx.field(name, |ctx| p.patch_into(v, mem, offset, map, ctx)),
+                        None => ctx.field(name, |_| Err(CodecError::not_found(self))),
+                    }
+                }
+                Ok(())
+            }
+            AdsType::Array { ty, dimensions, .. } => {
+                let len = self.len() as usize;
+                patch_array(ty, dimensions, patch, &mut mem[..len], offset, map, ctx)
+            }
+            _ => write_range(mem, offset, ctx, |w, ctx| self.to_writer(patch, w, map, ctx)),
+        }
+    }
+
+    // the type tree with comments, units and attributes of every field
+    pub fn metadata(&self, map: &CHashMap<String, AdsType>) -> Value {
+        let mut m = Map::new();
+        m.insert("type".to_string(), Value::String(self.to_string()));
+        match self {
+            AdsType::Enum { keys, .. } => {
+                let values = keys
+                    .iter()
+                    .map(|(k, v)| (v.to_string(), Value::from(*k)))
+                    .collect();
+                m.insert("values".to_string(), Value::Object(values));
+            }
+            AdsType::Struct { properties, .. } => {
+                let fields = properties
+                    .iter()
+                    .map(|p| (p.name.trim().to_string(), p.metadata(map)))
+                    .collect();
+                m.insert("fields".to_string(), Value::Object(fields));
             }
-            AdsType::Array { ty, bounds, .. } => (0..*bounds)
-                .map(|i| ty.to_writer(&data[i], w, map))
-                .collect(),
-            AdsType::Primitive(ref ty) => ty.to_writer(data, w, map),
+            AdsType::Array { ty, .. } => {
+                m.insert("element".to_string(), ty.metadata(map));
+            }
+            AdsType::Primitive(_) => (),
         }
+        Value::Object(m)
     }
 
     pub fn as_data_struct<R: ReadBytesExt>(
         &self,
         r: &mut R,
         map: &CHashMap<String, AdsType>,
-    ) -> Value {
+        format: &ValueFormat,
+    ) -> Result<Value, CodecError> {
         match self {
-            AdsType::Enum { .. } => read_ads_number::<i16, R>(r, &None),
-            AdsType::Struct { properties, .. } => Value::Object(
+            AdsType::Enum { keys, ty, .. } => {
+                let value = ty.as_data_struct(r, map, format)?;
+                let text = value
+                    .as_i64()
+                    .and_then(|k| keys.iter().find(|(key, _)| *key == k))
+                    .map(|(_, text)| text);
+                Ok(match text {
+                    Some(text) => match format.enums {
+                        EnumFormat::Name => Value::String(text.to_string()),
+                        EnumFormat::NameAndValue => {
+                            let mut m = Map::new();
+                            m.insert("name".to_string(), Value::String(text.to_string()));
+                            m.insert("value".to_string(), value);
+                            Value::Object(m)
+                        }
+                    },
+                    None => value,
+                })
+            }
+            AdsType::Struct { properties, .. } => {
+                let mut b = vec![0u8; self.len() as usize];
+                r.read_exact(&mut b).map_err(|e| CodecError::io(self, e))?;
                 properties
                     .iter()
-                    .map(|p| (p.name.trim().to_string(), p.ty.as_data_struct(r, map)))
-                    .collect(),
-            ),
+                    .map(|p| {
+                        let name = p.name.trim();
+                        p.as_data_struct(&b, map, format)
+                            .map(|v| (name.to_string(), v))
+                            .map_err(|e| e.field(name))
+                    })
+                    .collect::<Result<Map<String, Value>, CodecError>>()
+                    .map(Value::Object)
+            }
             AdsType::Array {
-                ref ty, ref bounds, ..
-            } => (0..*bounds).map(|_| ty.as_data_struct(r, map)).collect(),
-            AdsType::Primitive(ref ty) => ty.as_data_struct(r, map),
+                ref ty,
+                ref dimensions,
+                ..
+            } => read_array(ty, dimensions, r, map, format),
+            AdsType::Primitive(ref ty) => ty.as_data_struct(r, map, format),
         }
     }
 
@@ -243,10 +725,20 @@ impl AdsType {
             Value::Object(ref obj) => match obj {
                 obj if obj.contains_key("EnumInfo") => Some(AdsType::Enum {
                     name: name.text.trim().to_string(),
-                    keys: obj["EnumInfo"]
-                        .as_array()
-                        .expect("no array")
-                        .iter()
+                    ty: match obj.get("Type") {
+                        Some(t) => type_from_value(t, None),
+                        None => match bit_size {
+                            8 => AdsPlcType::USInt(None),
+                            32 => AdsPlcType::DInt(None),
+                            64 => AdsPlcType::LInt(None),
+                            _ => AdsPlcType::Int(None),
+                        },
+                    },
+                    keys: match obj.get("EnumInfo") {
+                        Some(Value::Array(ref a)) => a.clone(),
+                        Some(o) => vec![o.clone()],
+                        None => unreachable!(),
+                    }.iter()
                         .map(|f| {
                             let n = number_from_value(&f["Enum"]);
                             let s = f["Text"].as_str().unwrap().to_string();
@@ -266,14 +758,27 @@ impl AdsType {
                             }
                             _ => unreachable!(),
                         };
+                        let mut next_offs = 0;
                         sub_items
                             .iter()
                             .map(|f| {
                                 let n: Name = (&f["Name"]).into();
                                 let ty = &f["Type"];
+                                let bit_offs = match f.get("BitOffs") {
+                                    Some(o) => number_from_value(o),
+                                    None => next_offs,
+                                };
+                                let bit_size = number_from_value::<u32>(&f["BitSize"]);
+                                next_offs = bit_offs + bit_size;
                                 AdsStructProperties {
                                     name: n.text.to_string(),
-                                    ty: type_from_value(&ty, None),
+                                    bit_offs,
+                                    ty: if ty.get("@Pointer").is_some() {
+                                        AdsPlcType::Pointer(bit_size as usize / 8)
+                                    } else {
+                                        fit_string(type_from_value(&ty, None), bit_size)
+                                    },
+                                    metadata: Metadata::from_value(f),
                                 }
                             })
                             .collect()
@@ -289,14 +794,21 @@ impl AdsType {
                         Some(Value::Array(ref a)) => a.clone(),
                         _ => unreachable!(),
                     };
+                    let dimensions: Vec<ArrayDimension> = array_info
+                        .iter()
+                        .map(|f| ArrayDimension {
+                            lower_bound: number_from_value(&f["LBound"]),
+                            elements: number_from_value(&f["Elements"]),
+                        })
+                        .collect();
+                    let elements: usize = dimensions.iter().map(|d| d.elements).product();
+                    let ty = type_from_value(&obj["Type"], None);
                     Some(AdsType::Array {
-                        bounds: array_info.iter().fold(1, |acc, f| match f {
-                            Value::Object(ref o) => {
-                                acc * (o["Elements"].as_f64().unwrap() as usize)
-                            }
-                            _ => acc,
-                        }),
-                        ty: type_from_value(&obj["Type"], None),
+                        ty: match elements {
+                            0 => ty,
+                            n => fit_string(ty, bit_size / n as u32),
+                        },
+                        dimensions,
                         bit_size,
                     })
                 }
@@ -308,9 +820,9 @@ impl AdsType {
                     } else {
                         None
                     };
-                    Some(AdsType::Primitive(type_from_value(
-                        obj.get("Type").unwrap(),
-                        sri,
+                    Some(AdsType::Primitive(fit_string(
+                        type_from_value(obj.get("Type").unwrap(), sri),
+                        bit_size,
                     )))
                 }
                 _ => None,
@@ -323,12 +835,18 @@ impl AdsType {
         match self {
             AdsType::Primitive(p) => match p {
                 AdsPlcType::String(ref s) => (s + 1) as u32,
+                AdsPlcType::WString(ref s) => 2 * (s + 1) as u32,
                 AdsPlcType::Bool => 1,
+                AdsPlcType::Bit => 1,
                 AdsPlcType::SInt(_) => 1,
                 AdsPlcType::USInt(_) => 1,
                 AdsPlcType::Int(_) => 2,
                 AdsPlcType::UInt(_) => 2,
+                AdsPlcType::LInt(_) => 8,
+                AdsPlcType::ULInt(_) => 8,
                 AdsPlcType::LReal => 8,
+                AdsPlcType::LTime => 8,
+                AdsPlcType::Pointer(ref size) => *size as u32,
                 _ => 4,
             },
             AdsType::Array { bit_size, .. } => *bit_size / 8,
@@ -338,6 +856,325 @@ impl AdsType {
     }
 }
 
+impl AdsStructProperties {
+    fn to_writer(
+        &self,
+        data: &Value,
+        b: &mut [u8],
+        map: &CHashMap<String, AdsType>,
+        ctx: &mut WriteContext,
+    ) -> Result<(), CodecError> {
+        let i = (self.bit_offs / 8) as usize;
+        match self.ty {
+            AdsPlcType::Bit => {
+                let mask = 1 << (self.bit_offs % 8);
+                let bit = data
+                    .as_bool()
+                    .ok_or_else(|| CodecError::invalid_value(&self.ty, data))?;
+                if bit {
+                    b[i] |= mask;
+                } else {
+                    b[i] &= !mask;
+                }
+                Ok(())
+            }
+            ref ty => ty.to_writer(data, &mut &mut b[i..], map, ctx),
+        }
+    }
+
+    // mem and offset refer to the start of the struct
+    fn patch_into(
+        &self,
+        data: &Value,
+        mem: &mut [u8],
+        offset: usize,
+        map: &CHashMap<String, AdsType>,
+        ctx: &mut WriteContext,
+    ) -> Result<(), CodecError> {
+        let at = (self.bit_offs / 8) as usize;
+        if data.is_null() {
+            return match self.ty {
+                AdsPlcType::Bit => {
+                    self.write_default(mem, map)?;
+                    ctx.ranges.push(offset + at..offset + at + 1);
+                    Ok(())
+                }
+                ref ty => {
+                    let d = ty.default_bytes(map, self.metadata.default.as_ref())?;
+                    write_default(&mut mem[at..], offset + at, ctx, &d);
+                    Ok(())
+                }
+            };
+        }
+        let unscaled;
+        let data = match self.scale(map, &ctx.format) {
+            Some((factor, integer)) => {
+                unscaled = meta::unscale(data, factor, integer);
+                &unscaled
+            }
+            None => data,
+        };
+        match self.ty {
+            // the plc is written bytewise, so the neighbouring bits are written as well
+            AdsPlcType::Bit => {
+                self.to_writer(data, mem, map, ctx)?;
+                ctx.ranges.push(offset + at..offset + at + 1);
+                Ok(())
+            }
+            ref ty => ty.patch_into(data, &mut mem[at..], offset + at, map, ctx),
+        }
+    }
+
+    // b starts at the struct
+    fn write_default(
+        &self,
+        b: &mut [u8],
+        map: &CHashMap<String, AdsType>,
+    ) -> Result<(), CodecError> {
+        match self.ty {
+            AdsPlcType::Bit => {
+                let mut ctx = WriteContext::new(RangePolicy::Clamp, ValueFormat::default());
+                let bit = self.metadata.default.clone().unwrap_or(Value::Bool(false));
+                self.to_writer(&bit, b, map, &mut ctx)
+            }
+            ref ty => {
+                let at = (self.bit_offs / 8) as usize;
+                let d = ty.default_bytes(map, self.metadata.default.as_ref())?;
+                b[at..at + d.len()].clone_from_slice(&d);
+                Ok(())
+            }
+        }
+    }
+
+    fn as_data_struct(
+        &self,
+        b: &[u8],
+        map: &CHashMap<String, AdsType>,
+        format: &ValueFormat,
+    ) -> Result<Value, CodecError> {
+        let i = (self.bit_offs / 8) as usize;
+        match self.ty {
+            AdsPlcType::Bit => Ok(((b[i] >> (self.bit_offs % 8)) & 1 == 1).into()),
+            ref ty => {
+                let value = ty.as_data_struct(&mut &b[i..], map, format)?;
+                Ok(match self.scale(map, format) {
+                    Some((factor, _)) => meta::scale(value, factor),
+                    None => value,
+                })
+            }
+        }
+    }
+
+    // the factor and whether the raw values are integers, if scaling applies
+    fn scale(&self, map: &CHashMap<String, AdsType>, format: &ValueFormat) -> Option<(f64, bool)> {
+        match (format.units, self.metadata.scale) {
+            (UnitFormat::Scaled, Some(factor)) => self.ty.numeric(map).map(|i| (factor, i)),
+            _ => None,
+        }
+    }
+
+    fn metadata(&self, map: &CHashMap<String, AdsType>) -> Value {
+        let mut m = match self.ty.metadata(map) {
+            Value::Object(m) => m,
+            _ => Map::new(),
+        };
+        self.metadata.extend(&mut m);
+        Value::Object(m)
+    }
+}
+
+impl fmt::Display for AdsPlcType {
+    fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+        let (name, s) = match self {
+            AdsPlcType::Bool => ("BOOL", &None),
+            AdsPlcType::SInt(ref s) => ("SINT", s),
+            AdsPlcType::USInt(ref s) => ("USINT", s),
+            AdsPlcType::Int(ref s) => ("INT", s),
+            AdsPlcType::UInt(ref s) => ("UINT", s),
+            AdsPlcType::DInt(ref s) => ("DINT", s),
+            AdsPlcType::UDInt(ref s) => ("UDINT", s),
+            AdsPlcType::LInt(ref s) => ("LINT", s),
+            AdsPlcType::ULInt(ref s) => ("ULINT", s),
+            AdsPlcType::Real => ("REAL", &None),
+            AdsPlcType::LReal => ("LREAL", &None),
+            AdsPlcType::String(ref len) => return write!(f, "STRING({})", len),
+            AdsPlcType::WString(ref len) => return write!(f, "WSTRING({})", len),
+            AdsPlcType::Time => ("TIME", &None),
+            AdsPlcType::LTime => ("LTIME", &None),
+            AdsPlcType::TOD => ("TOD", &None),
+            AdsPlcType::Date => ("DATE", &None),
+            AdsPlcType::DT => ("DT", &None),
+            AdsPlcType::Bit => ("BIT", &None),
+            AdsPlcType::Pointer(_) => ("POINTER", &None),
+            AdsPlcType::Other { ref name, .. } => return write!(f, "{}", name.trim()),
+        };
+        match s {
+            Some(s) => write!(f, "{}({}..{})", name, s.min, s.max),
+            None => write!(f, "{}", name),
+        }
+    }
+}
+
+impl fmt::Display for AdsType {
+    fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+        match self {
+            AdsType::Enum { name, .. } | AdsType::Struct { name, .. } => write!(f, "{}", name),
+            AdsType::Array { dimensions, ty, .. } => {
+                let bounds: Vec<String> = dimensions
+                    .iter()
+                    .map(|d| {
+                        let upper = d.lower_bound + d.elements as i64 - 1;
+                        format!("{}..{}", d.lower_bound, upper)
+                    })
+                    .collect();
+                write!(f, "ARRAY [{}] OF {}", bounds.join(","), ty)
+            }
+            AdsType::Primitive(ref ty) => write!(f, "{}", ty),
+        }
+    }
+}
+
+impl ArrayDimension {
+    pub fn index(&self, i: usize) -> String {
+        (self.lower_bound + i as i64).to_string()
+    }
+}
+
+// arrays are stored row major, the last dimension changes fastest
+fn read_array<R: ReadBytesExt>(
+    ty: &AdsPlcType,
+    dimensions: &[ArrayDimension],
+    r: &mut R,
+    map: &CHashMap<String, AdsType>,
+    format: &ValueFormat,
+) -> Result<Value, CodecError> {
+    match dimensions.split_first() {
+        Some((d, rest)) => {
+            let elements = (0..d.elements).map(|i| {
+                read_array(ty, rest, r, map, format)
+                    .map(|v| (d.index(i), v))
+                    .map_err(|e| e.index(&d.index(i)))
+            });
+            match format.arrays {
+                ArrayFormat::Nested => elements
+                    .map(|e| e.map(|(_, v)| v))
+                    .collect::<Result<Vec<Value>, CodecError>>()
+                    .map(Value::Array),
+                ArrayFormat::Indexed => elements
+                    .collect::<Result<Map<String, Value>, CodecError>>()
+                    .map(Value::Object),
+            }
+        }
+        None => ty.as_data_struct(r, map, format),
+    }
+}
+
+// accepts plain json arrays as well as objects keyed by the plc index
+fn write_array<W: WriteBytesExt>(
+    ty: &AdsPlcType,
+    dimensions: &[ArrayDimension],
+    data: &Value,
+    w: &mut W,
+    map: &CHashMap<String, AdsType>,
+    ctx: &mut WriteContext,
+) -> Result<(), CodecError> {
+    match dimensions.split_first() {
+        Some((d, rest)) => {
+            for i in 0..d.elements {
+                let index = d.index(i);
+                let element = match data {
+                    Value::Object(_) => &data[&index],
+                    _ => &data[i],
+                };
+                ctx.index(&index, |ctx| write_array(ty, rest, element, w, map, ctx));
+            }
+            Ok(())
+        }
+        None => ty.to_writer(data, w, map, ctx),
+    }
+}
+
+// elements are addressed by position in json arrays and by plc index in objects
+fn patch_array(
+    ty: &AdsPlcType,
+    dimensions: &[ArrayDimension],
+    patch: &Value,
+    mem: &mut [u8],
+    offset: usize,
+    map: &CHashMap<String, AdsType>,
+    ctx: &mut WriteContext,
+) -> Result<(), CodecError> {
+    let (d, rest) = match dimensions.split_first() {
+        Some(d) => d,
+        None => return ty.patch_into(patch, mem, offset, map, ctx),
+    };
+    let elements: Vec<(String, Option<usize>, &Value)> = match patch {
+        Value::Array(ref v) => v
+            .iter()
+            .enumerate()
+            .map(|(i, v)| (d.index(i), Some(i).filter(|i| *i < d.elements), v))
+            .collect(),
+        Value::Object(ref o) => o
+            .iter()
+            .map(|(k, v)| {
+                let i = k
+                    .trim()
+                    .parse::<i64>()
+                    .ok()
+                    .map(|k| k - d.lower_bound)
+                    .filter(|i| *i >= 0 && (*i as usize) < d.elements);
+                (k.trim().to_string(), i.map(|i| i as usize), v)
+            })
+            .collect(),
+        _ => {
+            let expected = AdsType::Array {
+                dimensions: dimensions.to_vec(),
+                bit_size: (mem.len() * 8) as u32,
+                ty: ty.clone(),
+            };
+            return Err(CodecError::invalid_value(expected, patch));
+        }
+    };
+    let stride = mem.len() / d.elements;
+    for (index, i, v) in elements {
+        ctx.index(&index, |ctx| match i {
+            Some(i) => {
+                let at = i * stride;
+                let element = &mut mem[at..at + stride];
+                patch_array(ty, rest, v, element, offset + at, map, ctx)
+            }
+            None => {
+                let upper = d.lower_bound + d.elements as i64 - 1;
+                Err(CodecError::not_found(format!("ARRAY [{}..{}]", d.lower_bound, upper)))
+            }
+        });
+    }
+    Ok(())
+}
+
+fn write_default(mem: &mut [u8], offset: usize, ctx: &mut WriteContext, d: &[u8]) {
+    mem[..d.len()].clone_from_slice(d);
+    ctx.ranges.push(offset..offset + d.len());
+}
+
+// records the bytes written by f as touched
+fn write_range<F>(
+    mem: &mut [u8],
+    offset: usize,
+    ctx: &mut WriteContext,
+    f: F,
+) -> Result<(), CodecError>
+where
+    F: FnOnce(&mut &mut [u8], &mut WriteContext) -> Result<(), CodecError>,
+{
+    let available = mem.len();
+    let mut w = mem;
+    f(&mut w, ctx)?;
+    let written = available - w.len();
+    ctx.ranges.push(offset..offset + written);
+    Ok(())
+}
+
 impl<'a> From<&'a Value> for Name {
     fn from(a: &'a Value) -> Self {
         match a {
@@ -371,25 +1208,30 @@ impl From<(String, Option<String>, Option<SubRange>)> for AdsPlcType {
             "BYTE" => AdsPlcType::USInt(s.2),
             "WORD" => AdsPlcType::UInt(s.2),
             "DWORD" => AdsPlcType::UDInt(s.2),
+            "LWORD" => AdsPlcType::ULInt(s.2),
             "SINT" => AdsPlcType::SInt(s.2),
             "USINT" => AdsPlcType::USInt(s.2),
             "INT" => AdsPlcType::Int(s.2),
             "UINT" => AdsPlcType::UInt(s.2),
             "DINT" => AdsPlcType::DInt(s.2),
             "UDINT" => AdsPlcType::UDInt(s.2),
+            "LINT" => AdsPlcType::LInt(s.2),
+            "ULINT" => AdsPlcType::ULInt(s.2),
             "REAL" => AdsPlcType::Real,
             "LREAL" => AdsPlcType::LReal,
             "TIME" => AdsPlcType::Time,
+            "LTIME" => AdsPlcType::LTime,
             "TIME_OF_DAY" => AdsPlcType::TOD,
             "TOD" => AdsPlcType::TOD,
             "DATE" => AdsPlcType::Date,
             "DATE_AND_TIME" => AdsPlcType::DT,
             "DT" => AdsPlcType::DT,
+            "BIT" => AdsPlcType::Bit,
             st => {
-                if st.contains("STRING") && !st.contains("ARRAY") {
-                    let mut ost = st.replace("STRING(", "");
-                    ost = ost.replace(")", "");
-                    AdsPlcType::String(ost.parse().unwrap())
+                if st.contains("WSTRING") && !st.contains("ARRAY") {
+                    AdsPlcType::WString(string_len(st))
+                } else if st.contains("STRING") && !st.contains("ARRAY") {
+                    AdsPlcType::String(string_len(st))
                 } else {
                     AdsPlcType::Other {
                         name: st.to_string(),
@@ -400,3 +1242,163 @@ impl From<(String, Option<String>, Option<SubRange>)> for AdsPlcType {
         }
     }
 }
+
+// STRING and WSTRING without an explicit length default to 80 characters, a constant
+// like STRING(MAX_LEN) as well until fit_string sizes it
+fn string_len(st: &str) -> usize {
+    match (st.find('('), st.rfind(')')) {
+        (Some(b), Some(e)) if b < e => st[b + 1..e].trim().parse().unwrap_or(80),
+        _ => 80,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::testing::{other, prop};
+    use super::*;
+
+    fn sub_item(name: &str, ty: &str, bit_offs: u32, bit_size: u32) -> Value {
+        json!({"Name": name, "Type": ty, "BitOffs": bit_offs, "BitSize": bit_size})
+    }
+
+    #[test]
+    fn string_with_constant_length_takes_the_bit_size() {
+        let ty = json!({
+            "Name": {"#text": "ST_NAMES", "@Decoration": "1"},
+            "BitSize": 344,
+            "SubItem": [
+                sub_item("sName", "STRING(MAX_LEN)", 0, 168),
+                sub_item("wsName", "WSTRING(Param.Len)", 168, 176),
+            ]
+        });
+        match AdsType::from_value(&ty) {
+            Some(AdsType::Struct { properties, .. }) => {
+                assert_eq!(properties[0].ty, AdsPlcType::String(20));
+                assert_eq!(properties[1].ty, AdsPlcType::WString(10));
+            }
+            t => panic!("no struct {:?}", t),
+        }
+        assert_eq!(string_len("STRING(MAX_LEN)"), 80);
+        assert_eq!(string_len("STRING(12)"), 12);
+    }
+
+    // two flags, INTs at 2 and 4, a DINT at 8, a struct at 12 and ARRAY [1..4] OF INT at 16
+    fn patched(patch: Value) -> (Vec<Range<usize>>, Vec<u8>) {
+        let map = CHashMap::new();
+        map.insert(
+            "inner".to_string(),
+            AdsType::Struct {
+                name: "ST_INNER".to_string(),
+                bit_size: 32,
+                properties: vec![
+                    prop("nX", 0, AdsPlcType::Int(None)),
+                    prop("nY", 16, AdsPlcType::Int(None)),
+                ],
+            },
+        );
+        map.insert(
+            "values".to_string(),
+            AdsType::Array {
+                dimensions: vec![ArrayDimension {
+                    lower_bound: 1,
+                    elements: 4,
+                }],
+                bit_size: 64,
+                ty: AdsPlcType::Int(None),
+            },
+        );
+        let ty = AdsType::Struct {
+            name: "ST_OUTER".to_string(),
+            bit_size: 192,
+            properties: vec![
+                prop("bFlag", 0, AdsPlcType::Bit),
+                prop("bOther", 1, AdsPlcType::Bit),
+                prop("nA", 16, AdsPlcType::Int(None)),
+                prop("nB", 32, AdsPlcType::Int(None)),
+                prop("nC", 64, AdsPlcType::DInt(None)),
+                prop("stInner", 96, other("inner")),
+                prop("aValues", 128, other("values")),
+            ],
+        };
+        let mut mem = vec![0u8; 24];
+        mem[0] = 0b10;
+        let (ranges, clamped) = ty
+            .patch(&patch, &mut mem, &map, RangePolicy::Reject, ValueFormat::default())
+            .unwrap();
+        assert!(clamped.is_empty());
+        (ranges, mem)
+    }
+
+    #[test]
+    fn patching_a_flag_writes_its_byte() {
+        let (ranges, mem) = patched(json!({"bFlag": true}));
+        assert_eq!(ranges, vec![0..1]);
+        assert_eq!(mem[0], 0b11);
+        assert!(mem[1..].iter().all(|b| *b == 0));
+    }
+
+    #[test]
+    fn adjacent_fields_merge_into_one_range() {
+        let (ranges, mem) = patched(json!({"nA": 1, "nB": 2}));
+        assert_eq!(ranges, vec![2..6]);
+        assert_eq!(&mem[2..6], &[1, 0, 2, 0]);
+    }
+
+    #[test]
+    fn separate_fields_are_written_separately() {
+        let (ranges, mem) = patched(json!({"nC": 3, "nA": 1}));
+        assert_eq!(ranges, vec![2..4, 8..12]);
+        assert_eq!(&mem[8..12], &[3, 0, 0, 0]);
+        assert_eq!(&mem[4..8], &[0, 0, 0, 0]);
+    }
+
+    #[test]
+    fn nested_struct_and_array_paths() {
+        let (ranges, mem) = patched(json!({"stInner": {"nY": 7}, "aValues": {"2": 5}}));
+        assert_eq!(ranges, vec![14..16, 18..20]);
+        assert_eq!(&mem[12..20], &[0, 0, 7, 0, 0, 0, 5, 0]);
+        let (ranges, _) = patched(json!({"stInner": {"nY": 7}, "aValues": [1, 2]}));
+        assert_eq!(ranges, vec![14..20]);
+    }
+
+    #[test]
+    fn enums_reset_to_their_first_declared_value() {
+        let ty = json!({
+            "Name": {"#text": "E_STATE", "@Decoration": "9"},
+            "BitSize": 16,
+            "EnumInfo": [{"Enum": "5", "Text": "eIdle"}, {"Enum": "1", "Text": "eRun"}]
+        });
+        let ty = AdsType::from_value(&ty).unwrap();
+        let map = CHashMap::new();
+        assert_eq!(ty.default_bytes(&map, None).unwrap(), vec![5, 0]);
+        let format = ValueFormat::default();
+        let value = ty.as_data_struct(&mut &[1u8, 0][..], &map, &format).unwrap();
+        assert_eq!(value, json!("eRun"));
+    }
+
+    #[test]
+    fn counters_run_through_their_type() {
+        let map = CHashMap::new();
+        map.insert("T_COUNTER".to_string(), AdsType::Primitive(AdsPlcType::Int(None)));
+        assert_eq!(AdsPlcType::UInt(None).counter_values(&map), Some(1 << 16));
+        assert_eq!(other("T_COUNTER").counter_values(&map), Some(1 << 15));
+        assert_eq!(AdsPlcType::ULInt(None).counter_values(&map), Some(1 << 64));
+        assert_eq!(AdsPlcType::Real.counter_values(&map), None);
+    }
+
+    #[test]
+    fn times_past_u32_are_rejected_under_every_policy() {
+        let map = CHashMap::new();
+        for policy in &[RangePolicy::Clamp, RangePolicy::Reject, RangePolicy::PassThrough] {
+            for ty in &[AdsPlcType::Time, AdsPlcType::Date, AdsPlcType::DT] {
+                let mut ctx = WriteContext::new(*policy, ValueFormat::default());
+                let mut w = Vec::new();
+                assert!(ty.to_writer(&json!(1u64 << 32), &mut w, &map, &mut ctx).is_err());
+                let mut ctx = WriteContext::new(*policy, ValueFormat::default());
+                let mut w = Vec::new();
+                assert!(ty.to_writer(&json!(42), &mut w, &map, &mut ctx).is_ok());
+                assert_eq!(w, vec![42, 0, 0, 0]);
+            }
+        }
+    }
+}
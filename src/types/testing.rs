@@ -0,0 +1,47 @@
+use super::{AdsPlcType, AdsStructProperties, AdsType, AdsVersion, Metadata, Name, RootRole, Symbol};
+use std::collections::HashMap;
+
+pub fn prop(name: &str, bit_offs: u32, ty: AdsPlcType) -> AdsStructProperties {
+    AdsStructProperties {
+        name: name.to_string(),
+        bit_offs,
+        ty,
+        metadata: Metadata::default(),
+    }
+}
+
+pub fn other(reference: &str) -> AdsPlcType {
+    AdsPlcType::Other {
+        name: reference.to_string(),
+        reference: reference.to_string(),
+    }
+}
+
+// the types by key, every root is a symbol of the type with its name at offset 0
+pub fn version(types: Vec<(&str, AdsType)>, roots: &[(&str, RootRole)]) -> AdsVersion {
+    let mut version = AdsVersion {
+        map: types.into_iter().map(|(k, t)| (k.to_string(), t)).collect(),
+        symbols: Default::default(),
+        search_index: Default::default(),
+        roles: HashMap::new(),
+        heartbeat: None,
+        identity_symbol: None,
+        polls: Vec::new(),
+    };
+    for (root, role) in roots {
+        let symbol = Symbol {
+            index_group: 0x4020,
+            index_offset: 0,
+            name: Name {
+                text: root.to_string(),
+                decoration: None,
+            },
+            ty: other(root),
+            metadata: Metadata::default(),
+        };
+        version.symbols.insert(root.to_string(), symbol);
+        version.search_index.insert(root.to_string(), root.to_string());
+        version.roles.insert(root.to_string(), *role);
+    }
+    version
+}
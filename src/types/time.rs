@@ -0,0 +1,159 @@
+use super::AdsPlcType;
+use chrono::{NaiveDate, NaiveDateTime, NaiveTime, Timelike};
+use serde_json::Value;
+
+const NANOS_PER_MILLI: u64 = 1_000_000;
+const NANOS_PER_SEC: u64 = 1_000_000_000;
+const MILLIS_PER_DAY: u64 = 86_400_000;
+
+// TIME and TOD count milliseconds, LTIME nanoseconds, DATE and DT seconds since 1970. what
+// has no date or time of day stays the raw number
+pub fn to_iso(ty: &AdsPlcType, raw: u64) -> Value {
+    let s = match ty {
+        AdsPlcType::Time => Some(duration_to_iso(raw * NANOS_PER_MILLI)),
+        AdsPlcType::LTime => Some(duration_to_iso(raw)),
+        AdsPlcType::TOD => NaiveTime::from_num_seconds_from_midnight_opt(
+            (raw / 1000) as u32,
+            (raw % 1000 * NANOS_PER_MILLI) as u32,
+        ).map(|t| t.format("%H:%M:%S%.3f").to_string()),
+        AdsPlcType::Date => NaiveDateTime::from_timestamp_opt(raw as i64, 0)
+            .map(|d| d.format("%Y-%m-%d").to_string()),
+        AdsPlcType::DT => NaiveDateTime::from_timestamp_opt(raw as i64, 0)
+            .map(|d| d.format("%Y-%m-%dT%H:%M:%S").to_string()),
+        _ => unreachable!(),
+    };
+    s.map_or(Value::from(raw), Value::String)
+}
+
+// accepts the iso representation as well as the raw number, a TOD has to be within the day
+pub fn from_value(ty: &AdsPlcType, data: &Value) -> Option<u64> {
+    match ty {
+        AdsPlcType::TOD => raw_value(ty, data).filter(|&t| t < MILLIS_PER_DAY),
+        _ => raw_value(ty, data),
+    }
+}
+
+fn raw_value(ty: &AdsPlcType, data: &Value) -> Option<u64> {
+    let s = match data {
+        Value::String(ref s) => s.trim(),
+        _ => return data.as_u64(),
+    };
+    if let Ok(raw) = s.parse() {
+        return Some(raw);
+    }
+    match ty {
+        AdsPlcType::Time => duration_from_iso(s).map(|n| n / NANOS_PER_MILLI),
+        AdsPlcType::LTime => duration_from_iso(s),
+        AdsPlcType::TOD => NaiveTime::parse_from_str(s, "%H:%M:%S%.f")
+            .ok()
+            .map(|t| {
+                u64::from(t.num_seconds_from_midnight()) * 1000
+                    + u64::from(t.nanosecond()) / NANOS_PER_MILLI
+            }),
+        AdsPlcType::Date => NaiveDate::parse_from_str(s, "%Y-%m-%d")
+            .ok()
+            .and_then(|d| from_timestamp(d.and_hms(0, 0, 0))),
+        AdsPlcType::DT => NaiveDateTime::parse_from_str(s, "%Y-%m-%dT%H:%M:%S")
+            .ok()
+            .and_then(from_timestamp),
+        _ => unreachable!(),
+    }
+}
+
+fn from_timestamp(dt: NaiveDateTime) -> Option<u64> {
+    let t = dt.timestamp();
+    if t < 0 {
+        None
+    } else {
+        Some(t as u64)
+    }
+}
+
+fn duration_to_iso(nanos: u64) -> String {
+    let secs = nanos / NANOS_PER_SEC;
+    let frac = nanos % NANOS_PER_SEC;
+    let (d, h, m, s) = (secs / 86_400, secs / 3600 % 24, secs / 60 % 60, secs % 60);
+    let mut iso = "P".to_string();
+    if d > 0 {
+        iso += &format!("{}D", d);
+        if h == 0 && m == 0 && s == 0 && frac == 0 {
+            return iso;
+        }
+    }
+    iso.push('T');
+    if h > 0 {
+        iso += &format!("{}H", h);
+    }
+    if m > 0 {
+        iso += &format!("{}M", m);
+    }
+    if frac > 0 {
+        let f = format!("{:09}", frac);
+        iso += &format!("{}.{}S", s, f.trim_right_matches('0'));
+    } else if s > 0 || iso.ends_with('T') {
+        iso += &format!("{}S", s);
+    }
+    iso
+}
+
+// PnDTnHnMn.nS, fractions are only allowed on the seconds
+fn duration_from_iso(s: &str) -> Option<u64> {
+    let s = s.to_uppercase();
+    if !s.starts_with('P') || s.len() < 3 {
+        return None;
+    }
+    let mut nanos = 0u64;
+    let mut in_time = false;
+    let mut number = String::new();
+    for c in s[1..].chars() {
+        match c {
+            '0'..='9' | '.' | ',' => number.push(if c == ',' { '.' } else { c }),
+            'T' if !in_time && number.is_empty() => in_time = true,
+            'D' | 'H' | 'M' | 'S' => {
+                let unit = match (c, in_time) {
+                    ('D', false) => 86_400 * NANOS_PER_SEC,
+                    ('H', true) => 3600 * NANOS_PER_SEC,
+                    ('M', true) => 60 * NANOS_PER_SEC,
+                    ('S', true) => NANOS_PER_SEC,
+                    _ => return None,
+                };
+                let mut parts = number.splitn(2, '.');
+                let whole: u64 = parts.next()?.parse().ok()?;
+                nanos = nanos.checked_add(whole.checked_mul(unit)?)?;
+                if let Some(f) = parts.next() {
+                    if c != 'S' || f.is_empty() || f.len() > 9 {
+                        return None;
+                    }
+                    let f: u64 = format!("{:0<9}", f).parse().ok()?;
+                    nanos += f;
+                }
+                number.clear();
+            }
+            _ => return None,
+        }
+    }
+    if number.is_empty() {
+        Some(nanos)
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn time_of_day_outside_the_day_stays_raw() {
+        assert_eq!(to_iso(&AdsPlcType::TOD, 3_723_004), json!("01:02:03.004"));
+        assert_eq!(to_iso(&AdsPlcType::TOD, 86_400_000), json!(86_400_000));
+        assert_eq!(to_iso(&AdsPlcType::DT, 0), json!("1970-01-01T00:00:00"));
+    }
+
+    #[test]
+    fn time_of_day_is_written_within_the_day() {
+        assert_eq!(from_value(&AdsPlcType::TOD, &json!("23:59:59.999")), Some(86_399_999));
+        assert_eq!(from_value(&AdsPlcType::TOD, &json!(86_400_000)), None);
+        assert_eq!(from_value(&AdsPlcType::Time, &json!(86_400_000)), Some(86_400_000));
+    }
+}
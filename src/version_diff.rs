@@ -0,0 +1,343 @@
+use chashmap::CHashMap;
+use serde_json;
+use std::collections::BTreeSet;
+use std::fmt;
+use types::{AdsPlcType, AdsType, AdsVersion, RootRole};
+
+#[derive(Debug, Copy, Clone, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ChangeKind {
+    Added,
+    Removed,
+    Resized,
+    Moved,
+    Retyped,
+}
+
+// where a field or symbol lies, offsets are absolute within the root
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Layout {
+    pub offset: u32,
+    pub size: u32,
+    #[serde(rename = "type")]
+    pub ty: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Change {
+    pub path: String,
+    pub kind: ChangeKind,
+    pub old: Option<Layout>,
+    pub new: Option<Layout>,
+    pub breaking: bool,
+}
+
+impl fmt::Display for Layout {
+    fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+        write!(f, "{} @ {} ({} bytes)", self.ty, self.offset, self.size)
+    }
+}
+
+impl fmt::Display for Change {
+    fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+        let kind = serde_json::to_value(self.kind).unwrap();
+        write!(f, "{:<8} {}", kind.as_str().unwrap_or(""), self.path)?;
+        match (&self.old, &self.new) {
+            (Some(old), Some(new)) => write!(f, ": {} -> {}", old, new)?,
+            (Some(old), None) => write!(f, ": {}", old)?,
+            (None, Some(new)) => write!(f, ": {}", new)?,
+            (None, None) => (),
+        }
+        if self.breaking {
+            write!(f, " BREAKING")?;
+        }
+        Ok(())
+    }
+}
+
+struct Diff<'a> {
+    old: &'a CHashMap<String, AdsType>,
+    new: &'a CHashMap<String, AdsType>,
+    changes: Vec<Change>,
+    // the retain and exchange structs must keep their layout
+    breaking: bool,
+}
+
+// resolves references so structs and arrays can be compared by content
+fn resolve<T, F>(ty: &AdsPlcType, map: &CHashMap<String, AdsType>, f: F) -> Option<T>
+where
+    F: FnOnce(&AdsType) -> T,
+{
+    match ty {
+        AdsPlcType::Other { ref reference, .. } => {
+            map.get(&reference.trim().to_string()).map(|t| f(&*t))
+        }
+        ty => Some(f(&AdsType::Primitive(ty.clone()))),
+    }
+}
+
+fn layout(ty: &AdsType, offset: u32) -> Layout {
+    Layout {
+        offset,
+        size: ty.len(),
+        ty: ty.to_string(),
+    }
+}
+
+impl<'a> Diff<'a> {
+    fn push(&mut self, path: &str, kind: ChangeKind, old: Option<Layout>, new: Option<Layout>) {
+        let breaking = self.breaking && kind != ChangeKind::Added;
+        self.changes.push(Change {
+            path: path.to_string(),
+            kind,
+            old,
+            new,
+            breaking,
+        });
+    }
+
+    // old and new lie at the given offsets, moves are already reported by the caller
+    fn compare(&mut self, path: &str, old: &AdsType, new: &AdsType, offsets: (u32, u32)) {
+        let (old_layout, new_layout) = (layout(old, offsets.0), layout(new, offsets.1));
+        match (old, new) {
+            (
+                AdsType::Struct {
+                    name: old_name,
+                    properties: old_props,
+                    ..
+                },
+                AdsType::Struct {
+                    name: new_name,
+                    properties: new_props,
+                    ..
+                },
+            ) if old_name == new_name => {
+                if old_layout.size != new_layout.size {
+                    self.push(path, ChangeKind::Resized, Some(old_layout), Some(new_layout));
+                }
+                for p in old_props {
+                    let name = p.name.trim();
+                    let field = format!("{}.{}", path, name);
+                    let old_offset = offsets.0 + p.bit_offs / 8;
+                    let n = match new_props.iter().find(|n| n.name.trim() == name) {
+                        Some(n) => n,
+                        None => {
+                            let l = resolve(&p.ty, self.old, |t| layout(t, old_offset));
+                            self.push(&field, ChangeKind::Removed, l, None);
+                            continue;
+                        }
+                    };
+                    let new_offset = offsets.1 + n.bit_offs / 8;
+                    // positions are compared within the struct, so a moved struct
+                    // does not report all of its fields again
+                    if p.bit_offs != n.bit_offs {
+                        let old_l = resolve(&p.ty, self.old, |t| layout(t, old_offset));
+                        let new_l = resolve(&n.ty, self.new, |t| layout(t, new_offset));
+                        self.push(&field, ChangeKind::Moved, old_l, new_l);
+                    }
+                    self.compare_plc(&field, &p.ty, &n.ty, (old_offset, new_offset));
+                }
+                for n in new_props {
+                    let name = n.name.trim();
+                    if !old_props.iter().any(|p| p.name.trim() == name) {
+                        let offset = offsets.1 + n.bit_offs / 8;
+                        let l = resolve(&n.ty, self.new, |t| layout(t, offset));
+                        self.push(&format!("{}.{}", path, name), ChangeKind::Added, None, l);
+                    }
+                }
+            }
+            (
+                AdsType::Array {
+                    dimensions: old_dims,
+                    ty: old_ty,
+                    ..
+                },
+                AdsType::Array {
+                    dimensions: new_dims,
+                    ty: new_ty,
+                    ..
+                },
+            ) => {
+                if old_dims != new_dims {
+                    self.push(path, ChangeKind::Resized, Some(old_layout), Some(new_layout));
+                }
+                self.compare_plc(&format!("{}[]", path), old_ty, new_ty, offsets);
+            }
+            _ if old_layout.ty != new_layout.ty => {
+                self.push(path, ChangeKind::Retyped, Some(old_layout), Some(new_layout));
+            }
+            _ if old_layout.size != new_layout.size => {
+                self.push(path, ChangeKind::Resized, Some(old_layout), Some(new_layout));
+            }
+            _ => (),
+        }
+    }
+
+    fn compare_plc(&mut self, path: &str, old: &AdsPlcType, new: &AdsPlcType, offsets: (u32, u32)) {
+        let old_map = self.old;
+        let new_map = self.new;
+        let old_guard = match old {
+            AdsPlcType::Other { ref reference, .. } => old_map.get(&reference.trim().to_string()),
+            _ => None,
+        };
+        let new_guard = match new {
+            AdsPlcType::Other { ref reference, .. } => new_map.get(&reference.trim().to_string()),
+            _ => None,
+        };
+        let old_primitive = AdsType::Primitive(old.clone());
+        let new_primitive = AdsType::Primitive(new.clone());
+        let old_ty = match old_guard {
+            Some(ref t) => &**t,
+            None => &old_primitive,
+        };
+        let new_ty = match new_guard {
+            Some(ref t) => &**t,
+            None => &new_primitive,
+        };
+        self.compare(path, old_ty, new_ty, offsets);
+    }
+}
+
+// every root of both versions, the roles of the old version decide what breaks
+pub fn diff(old: &AdsVersion, new: &AdsVersion) -> Vec<Change> {
+    let roots: BTreeSet<String> = old
+        .search_index
+        .clone()
+        .into_iter()
+        .chain(new.search_index.clone())
+        .map(|(k, _)| k)
+        .collect();
+    let mut d = Diff {
+        old: &old.map,
+        new: &new.map,
+        changes: Vec::new(),
+        breaking: false,
+    };
+    for root in roots {
+        d.breaking = match old.roles.get(&root).or_else(|| new.roles.get(&root)) {
+            Some(RootRole::Retain) | Some(RootRole::FromPlc) | Some(RootRole::ToPlc) => true,
+            _ => false,
+        };
+        let symbol_offset = |v: &AdsVersion| {
+            v.symbols
+                .get(&root)
+                .map(|s| (s.index_group, s.index_offset))
+        };
+        let old_key = old.search_index.get(&root).map(|k| k.to_string());
+        let new_key = new.search_index.get(&root).map(|k| k.to_string());
+        let old_ty = old_key.as_ref().and_then(|k| old.map.get(k));
+        let new_ty = new_key.as_ref().and_then(|k| new.map.get(k));
+        match (old_ty, new_ty) {
+            (Some(o), Some(n)) => {
+                let (old_symbol, new_symbol) = (symbol_offset(old), symbol_offset(new));
+                if let (Some(os), Some(ns)) = (old_symbol, new_symbol) {
+                    if os != ns {
+                        let mut old_l = layout(&o, os.1);
+                        let mut new_l = layout(&n, ns.1);
+                        old_l.ty = format!("{} in group {:#x}", old_l.ty, os.0);
+                        new_l.ty = format!("{} in group {:#x}", new_l.ty, ns.0);
+                        d.push(&root, ChangeKind::Moved, Some(old_l), Some(new_l));
+                    }
+                }
+                d.compare(&root, &o, &n, (0, 0));
+            }
+            (Some(o), None) => d.push(&root, ChangeKind::Removed, Some(layout(&o, 0)), None),
+            (None, Some(n)) => d.push(&root, ChangeKind::Added, None, Some(layout(&n, 0))),
+            (None, None) => (),
+        }
+    }
+    d.changes
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use types::testing::{prop, version};
+    use types::AdsStructProperties;
+
+    fn root(name: &str, bit_size: u32, properties: Vec<AdsStructProperties>) -> AdsType {
+        AdsType::Struct {
+            name: name.to_string(),
+            bit_size,
+            properties,
+        }
+    }
+
+    fn changes(old: &AdsVersion, new: &AdsVersion) -> Vec<(String, ChangeKind, bool)> {
+        diff(old, new)
+            .into_iter()
+            .map(|c| (c.path, c.kind, c.breaking))
+            .collect()
+    }
+
+    #[test]
+    fn retain_changes_break_but_additions_do_not() {
+        let old = root(
+            "ST_RETAIN",
+            48,
+            vec![
+                prop("nA", 0, AdsPlcType::Int(None)),
+                prop("nB", 16, AdsPlcType::Int(None)),
+                prop("nC", 32, AdsPlcType::Int(None)),
+            ],
+        );
+        let new = root(
+            "ST_RETAIN",
+            64,
+            vec![
+                prop("nA", 0, AdsPlcType::DInt(None)),
+                prop("nC", 32, AdsPlcType::Int(None)),
+                prop("nD", 48, AdsPlcType::Int(None)),
+            ],
+        );
+        let old = version(vec![("RETAIN", old)], &[("RETAIN", RootRole::Retain)]);
+        let new = version(vec![("RETAIN", new)], &[("RETAIN", RootRole::Retain)]);
+        let expected = vec![
+            ("RETAIN".to_string(), ChangeKind::Resized, true),
+            ("RETAIN.nA".to_string(), ChangeKind::Retyped, true),
+            ("RETAIN.nB".to_string(), ChangeKind::Removed, true),
+            ("RETAIN.nD".to_string(), ChangeKind::Added, false),
+        ];
+        assert_eq!(changes(&old, &new), expected);
+    }
+
+    #[test]
+    fn data_roots_never_break() {
+        let old = root(
+            "ST_DATA",
+            32,
+            vec![
+                prop("nA", 0, AdsPlcType::Int(None)),
+                prop("nB", 16, AdsPlcType::Int(None)),
+            ],
+        );
+        let new = root(
+            "ST_DATA",
+            32,
+            vec![
+                prop("nB", 0, AdsPlcType::Int(None)),
+                prop("nA", 16, AdsPlcType::Int(None)),
+            ],
+        );
+        let gone = root("ST_GONE", 16, vec![prop("n", 0, AdsPlcType::Int(None))]);
+        let old = version(
+            vec![("DATA", old), ("GONE", gone)],
+            &[("DATA", RootRole::Data), ("GONE", RootRole::Data)],
+        );
+        let new = version(vec![("DATA", new)], &[("DATA", RootRole::Data)]);
+        let expected = vec![
+            ("DATA.nA".to_string(), ChangeKind::Moved, false),
+            ("DATA.nB".to_string(), ChangeKind::Moved, false),
+            ("GONE".to_string(), ChangeKind::Removed, false),
+        ];
+        assert_eq!(changes(&old, &new), expected);
+    }
+
+    #[test]
+    fn unchanged_versions_have_no_changes() {
+        let ty = root("ST_RETAIN", 16, vec![prop("nA", 0, AdsPlcType::Int(None))]);
+        let old = version(vec![("RETAIN", ty.clone())], &[("RETAIN", RootRole::Retain)]);
+        let new = version(vec![("RETAIN", ty)], &[("RETAIN", RootRole::Retain)]);
+        assert!(diff(&old, &new).is_empty());
+    }
+}
@@ -7,18 +7,20 @@ use actix::ActorFuture;
 use actix::AsyncContext;
 use actix_web::{ws, Error, HttpRequest, HttpResponse};
 use chashmap::CHashMap;
-use serde_json::{self, Value};
+use identify::PlcStatus;
+use serde_json::{self, Map, Value};
 use std::fmt;
 use std::io;
 use std::sync::{Arc, RwLock, RwLockReadGuard};
+use types::{insert_path, AdsVersion, RangePolicy, ValueFormat};
 use ws_ads::AdsToWsMultiplexer;
 
 pub enum WsToAdsClient {
     Register(Addr<Ws>),
     Unregister(Addr<Ws>),
-    Resolve(Schema),
-    Mutation(Value),
-    Subscription(Schema, Addr<Ws>),
+    Resolve(Schema, ValueFormat),
+    Mutation(Value, ValueFormat, Option<RangePolicy>),
+    Subscription(Schema, Addr<Ws>, ValueFormat),
 }
 
 pub struct AdsToWsClient(pub String);
@@ -33,14 +35,30 @@ impl Handler<AdsToWsClient> for Ws {
         ctx.text(m.0);
     }
 }
+
+// the plc was removed or reconnected, the client has to connect again
+pub struct CloseWs;
+
+impl Message for CloseWs {
+    type Result = ();
+}
+
+impl Handler<CloseWs> for Ws {
+    type Result = ();
+    fn handle(&mut self, _: CloseWs, ctx: &mut Self::Context) -> Self::Result {
+        self.c = None;
+        ctx.close(None);
+        ctx.stop();
+    }
+}
 impl fmt::Debug for WsToAdsClient {
     fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
         match self {
             WsToAdsClient::Register(_) => write!(f, "Register"),
             WsToAdsClient::Unregister(_) => write!(f, "Unregister"),
-            WsToAdsClient::Resolve(rest) => write!(f, "R: {:?}", rest),
-            WsToAdsClient::Mutation(rest) => write!(f, "M: {:?}", rest),
-            WsToAdsClient::Subscription(rest, _) => write!(f, "S: {:?}", rest),
+            WsToAdsClient::Resolve(rest, _) => write!(f, "R: {:?}", rest),
+            WsToAdsClient::Mutation(rest, _, _) => write!(f, "M: {:?}", rest),
+            WsToAdsClient::Subscription(rest, _, _) => write!(f, "S: {:?}", rest),
         }
     }
 }
@@ -50,16 +68,30 @@ impl Message for WsToAdsClient {
 }
 
 pub struct WsState {
-    config: RwLock<Vec<PlcSetting>>,
-    sender: CHashMap<[u8; 8], Addr<AdsToWsMultiplexer>>,
+    pub config: RwLock<Vec<PlcSetting>>,
+    pub sender: CHashMap<[u8; 8], Addr<AdsToWsMultiplexer>>,
+    pub versions: CHashMap<u32, Arc<AdsVersion>>,
+    pub status: CHashMap<[u8; 8], PlcStatus>,
 }
 
 impl WsState {
     pub fn new(
         config: RwLock<Vec<PlcSetting>>,
         sender: CHashMap<[u8; 8], Addr<AdsToWsMultiplexer>>,
+        versions: CHashMap<u32, Arc<AdsVersion>>,
     ) -> Self {
-        WsState { config, sender }
+        WsState {
+            config,
+            sender,
+            versions,
+            status: CHashMap::new(),
+        }
+    }
+    pub fn running_version(&self, conn: &[u8; 8]) -> Option<u32> {
+        match self.status.get(conn).map(|s| s.clone()) {
+            Some(PlcStatus::Running(n)) => Some(n),
+            _ => None,
+        }
     }
     pub fn config<'a>(&'a self) -> RwLockReadGuard<'a, Vec<PlcSetting>> {
         self.config.read().unwrap()
@@ -68,34 +100,112 @@ impl WsState {
 
 pub struct Ws {
     plc_conn: [u8; 8],
+    format: ValueFormat,
+    range: Option<RangePolicy>,
     c: Option<Addr<AdsToWsMultiplexer>>,
 }
 
 impl Ws {
     #[cfg_attr(feature = "cargo-clippy", allow(needless_pass_by_value))]
     pub fn ws_index(r: HttpRequest<Arc<WsState>>) -> Result<HttpResponse, Error> {
-        let (_, plc_conn) = ({
+        let plc_conn = {
             let m = r.match_info();
             let net_id = m.query::<String>("net_id")?;
             let port = m.query::<u16>("port")?;
+            let conn = (net_id.as_str(), port).as_plc_conn();
             let lg = r.state().config.read().unwrap();
-            let mys = lg.iter().find(|x| x.ams_net_id == net_id);
-            match mys {
-                Some(c) => Ok((c.version, (net_id, port).as_plc_conn())),
+            match lg.iter().filter_map(|x| x.as_plc_conn()).find(|c| Some(*c) == conn) {
+                Some(c) => c,
                 None => {
                     let ioe: io::Error = io::ErrorKind::NotFound.into();
-                    Err(ioe)
+                    return Err(ioe.into());
                 }
             }
-        })?;
-        ws::start(&r, Ws { plc_conn, c: None })
+        };
+        // plcs that are refused or not identified yet have no multiplexer
+        if r.state().sender.get(&plc_conn).is_none() {
+            let status = r.state().status.get(&plc_conn).map(|s| s.clone());
+            return match status {
+                Some(PlcStatus::Refused(reason)) => {
+                    Ok(HttpResponse::Conflict().body(format!("the plc is refused: {}", reason)))
+                }
+                Some(PlcStatus::Identifying) => {
+                    Ok(HttpResponse::ServiceUnavailable().body("the plc is being identified"))
+                }
+                Some(PlcStatus::Unreachable) => {
+                    Ok(HttpResponse::ServiceUnavailable().body("the plc can not be reached"))
+                }
+                _ => {
+                    let ioe: io::Error = io::ErrorKind::NotFound.into();
+                    Err(ioe.into())
+                }
+            };
+        }
+        let format = ValueFormat::from_query(&r.query());
+        let range = RangePolicy::from_query(&r.query());
+        ws::start(
+            &r,
+            Ws {
+                plc_conn,
+                format,
+                range,
+                c: None,
+            },
+        )
+    }
+}
+
+// comments, units and attributes of every root of the plc's version
+#[cfg_attr(feature = "cargo-clippy", allow(needless_pass_by_value))]
+pub fn meta_index(r: HttpRequest<Arc<WsState>>) -> Result<HttpResponse, Error> {
+    let version = {
+        let m = r.match_info();
+        let net_id = m.query::<String>("net_id")?;
+        let port = m.query::<u16>("port")?;
+        let lg = r.state().config.read().unwrap();
+        (net_id.as_str(), port)
+            .as_plc_conn()
+            .and_then(|conn| {
+                // the identified version while the plc runs, else the configured one
+                lg.iter()
+                    .find(|x| x.as_plc_conn() == Some(conn))
+                    .and_then(|c| r.state().running_version(&conn).or(c.version))
+            })
+            .and_then(|n| r.state().versions.get(&n).map(|v| v.clone()))
+    };
+    let version = match version {
+        Some(v) => v,
+        None => {
+            let ioe: io::Error = io::ErrorKind::NotFound.into();
+            return Err(ioe.into());
+        }
+    };
+    let mut roots = Map::new();
+    for (name, key) in version.search_index.clone() {
+        let mut meta = match version.map.get(&key) {
+            Some(ty) => match ty.metadata(&version.map) {
+                Value::Object(m) => m,
+                _ => Map::new(),
+            },
+            None => continue,
+        };
+        if let Some(symbol) = version.symbols.get(&name) {
+            symbol.metadata.extend(&mut meta);
+        }
+        roots.insert(name, Value::Object(meta));
     }
+    Ok(HttpResponse::Ok()
+        .content_type("application/json")
+        .body(Value::Object(roots).to_string()))
 }
 
 impl Actor for Ws {
     type Context = ws::WebsocketContext<Ws, Arc<WsState>>;
     fn started(&mut self, ctx: &mut Self::Context) {
-        let a = ctx.state().sender.get(&self.plc_conn).unwrap().clone();
+        let a = match ctx.state().sender.get(&self.plc_conn).map(|a| a.clone()) {
+            Some(a) => a,
+            None => return ctx.stop(),
+        };
         a.do_send(WsToAdsClient::Register(ctx.address()));
         self.c = Some(a);
     }
@@ -110,25 +220,54 @@ impl Actor for Ws {
 impl StreamHandler<ws::Message, ws::ProtocolError> for Ws {
     fn handle(&mut self, msg: ws::Message, ctx: &mut Self::Context) {
         let state = ctx.state().clone();
-        let sender = &mut *state.sender.get_mut(&self.plc_conn).unwrap();
+        // the plc may have been removed by a reload meanwhile
+        let sender = match state.sender.get(&self.plc_conn) {
+            Some(sender) => sender.clone(),
+            None => return ctx.stop(),
+        };
         let a = ctx.address();
+        let format = self.format;
         match msg {
             ws::Message::Ping(msg) => ctx.pong(&msg),
             ws::Message::Text(text) => match text.chars().position(|e| e == ':') {
                 Some(_) => {
-                    if let Ok(mutation) = serde_json::from_str::<Value>(&text) {
-                        sender.do_send(WsToAdsClient::Mutation(mutation));
+                    if let Ok(mut mutation) = serde_json::from_str::<Value>(&text) {
+                        // "$range" overrides the range policy for this mutation only
+                        let range = mutation
+                            .as_object_mut()
+                            .and_then(|m| m.remove("$range"))
+                            .and_then(|r| r.as_str().and_then(RangePolicy::from_name))
+                            .or(self.range);
+                        // "$reset" lists paths to put back to their initial value
+                        let reset = mutation.as_object_mut().and_then(|m| m.remove("$reset"));
+                        if let Some(Value::Array(paths)) = reset {
+                            for path in paths.iter().filter_map(|p| p.as_str()) {
+                                if !path.trim().is_empty() {
+                                    insert_path(&mut mutation, path, Value::Null);
+                                }
+                            }
+                        }
+                        let msg = WsToAdsClient::Mutation(mutation, format, range);
+                        ctx.spawn(
+                            wrap_future(sender.send(msg))
+                                .map(|f, _, ctx: &mut Self::Context| {
+                                    if let Ok(errors) = f {
+                                        ctx.text(errors)
+                                    }
+                                })
+                                .map_err(|_, _, _| println!("error on {} {}", line!(), file!())),
+                        );
                     }
                 }
                 None => {
                     match schema_parser(&text) {
                         Either::Resolve(v) => ctx.spawn(
-                            wrap_future(sender.send(WsToAdsClient::Resolve(v)))
+                            wrap_future(sender.send(WsToAdsClient::Resolve(v, format)))
                                 .map(|f, _, ctx: &mut Self::Context| ctx.text(f.unwrap()))
                                 .map_err(|_, _, _| println!("error on {} {}", line!(), file!())),
                         ),
                         Either::Subscription(v) => ctx.spawn(
-                            wrap_future(sender.send(WsToAdsClient::Subscription(v, a)))
+                            wrap_future(sender.send(WsToAdsClient::Subscription(v, a, format)))
                                 .map(|_, _, _| {})
                                 .map_err(|_, _, _| println!("error on {} {}", line!(), file!())),
                         ),
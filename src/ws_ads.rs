@@ -1,16 +1,18 @@
 use actix::fut::{wrap_future, ActorFuture};
 use actix::prelude::*;
-use byteorder::{ByteOrder, LittleEndian};
-use futures::{future, Future};
-use json_diff::{merge, merge_schemas, merge_values, Schema};
+use futures::future::{self, Either};
+use futures::Future;
+use json_diff::{merge_schemas, merge_values, Schema};
 use networking::{AdsReadReq, AdsReadRes, AdsWriteReq, Client, WsMultiplexerRegister};
+use rand::{self, Rng};
 use serde_json::{self, to_string, Value};
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet, VecDeque};
 use std::sync::Arc;
 use std::time::Duration;
-use types::Symbol;
-use types::{AdsType, AdsVersion};
-use ws::{AdsToWsClient, Ws, WsToAdsClient};
+use types::{
+    AdsType, AdsVersion, CodecError, RangePolicy, RootRole, ValueFormat, WriteContext,
+};
+use ws::{AdsToWsClient, CloseWs, Ws, WsToAdsClient};
 
 struct HeartBeat;
 
@@ -18,50 +20,66 @@ impl Message for HeartBeat {
     type Result = ();
 }
 
+// sent when the configuration or the version file of the plc changed
+pub enum Reload {
+    Version(Arc<AdsVersion>, RangePolicy, usize),
+    Stop,
+}
+
+impl Message for Reload {
+    type Result = ();
+}
+
+// the connection of the multiplexer, to identify the program on the plc again
+pub struct ClientOf;
+
+impl Message for ClientOf {
+    type Result = Addr<Client>;
+}
+
+// the raw bytes of every root, keyed by its name
 #[derive(Debug)]
-#[allow(non_snake_case)]
 pub struct AdsMemory {
     pub data: Value,
-    pub ST_ADS_TO_BC: Vec<u8>,
-    pub ST_ADS_FROM_BC: Vec<u8>,
-    pub ST_RETAIN_DATA: Vec<u8>,
+    pub memory: HashMap<String, Vec<u8>>,
 }
 
 impl AdsMemory {
-    pub fn by_str_mut(&mut self, s: &str) -> &mut [u8] {
-        match s.trim() {
-            "ST_ADS_TO_BC" => &mut self.ST_ADS_TO_BC,
-            "ST_ADS_FROM_BC" => &mut self.ST_ADS_FROM_BC,
-            "ST_RETAIN_DATA" => &mut self.ST_RETAIN_DATA,
-            _ => unreachable!(),
+    // every root zeroed until it is read from the plc
+    pub fn new(version: &AdsVersion) -> Self {
+        let mut data = Value::Object(serde_json::Map::new());
+        let mut memory = HashMap::new();
+        for (name, key) in version.search_index.clone() {
+            let value: &AdsType = &*version.map.get(&key).unwrap();
+            let v = vec![0u8; value.len() as usize];
+            data[&name] = value
+                .as_data_struct(&mut &v[..], &version.map, &ValueFormat::default())
+                .unwrap();
+            memory.insert(name, v);
         }
+        AdsMemory { data, memory }
     }
-}
 
-#[derive(Debug, Clone)]
-pub struct AdsStructMap {
-    pub st_ads_to_bc: Symbol,
-    pub st_retain_data: Symbol,
-}
-
-impl AdsStructMap {
-    pub fn by_str(&self, s: &str) -> &Symbol {
-        match s.trim() {
-            "ST_ADS_TO_BC" => &self.st_ads_to_bc,
-            "ST_RETAIN_DATA" => &self.st_retain_data,
-            _ => unreachable!(),
-        }
+    pub fn by_str_mut(&mut self, s: &str) -> Option<&mut Vec<u8>> {
+        self.memory.get_mut(s.trim())
     }
 }
 
 pub struct AdsToWsMultiplexer {
-    pub subscription_map: HashMap<Addr<Ws>, Schema>,
+    pub subscription_map: HashMap<Addr<Ws>, (Schema, ValueFormat)>,
     pub ws_clients: Vec<Addr<Ws>>,
     pub client: Addr<Client>,
     pub data: AdsMemory,
     pub version: Arc<AdsVersion>,
-    pub struct_map: AdsStructMap,
+    pub range_policy: RangePolicy,
     count: u32,
+    // roots waiting to be read, at most max_reads are read at once
+    max_reads: usize,
+    queue: VecDeque<String>,
+    reading: HashSet<String>,
+    // reads of a replaced version that are still out
+    stale: usize,
+    generation: u32,
 }
 
 impl AdsToWsMultiplexer {
@@ -69,7 +87,8 @@ impl AdsToWsMultiplexer {
         client: Addr<Client>,
         data: AdsMemory,
         version: Arc<AdsVersion>,
-        struct_map: AdsStructMap,
+        range_policy: RangePolicy,
+        max_reads: usize,
     ) -> Self {
         AdsToWsMultiplexer {
             subscription_map: HashMap::new(),
@@ -77,8 +96,140 @@ impl AdsToWsMultiplexer {
             client,
             data,
             version,
-            struct_map,
+            range_policy,
             count: 0,
+            max_reads,
+            queue: VecDeque::new(),
+            reading: HashSet::new(),
+            stale: 0,
+            generation: 0,
+        }
+    }
+
+    fn update_memory(&mut self, name: &str, offset: usize, bytes: &[u8]) {
+        if let Some(key) = self.version.search_index.get(&name.to_string()) {
+            let ty: &AdsType = &self.version.map.get(&*key).unwrap();
+            let map = &self.version.map;
+            let mem = match self.data.by_str_mut(name) {
+                Some(mem) => mem,
+                None => return,
+            };
+            let old = mem.to_vec();
+            mem[offset..offset + bytes.len()].clone_from_slice(bytes);
+            let new_data = match ty.as_data_struct(&mut &mem[..], map, &ValueFormat::default()) {
+                Ok(data) => data,
+                Err(e) => {
+                    error!("can not decode {}", e.field(name));
+                    return;
+                }
+            };
+            handle_subscriptions(&self.subscription_map, name, |format| {
+                let decode = |b: &[u8]| {
+                    let data = ty.as_data_struct(&mut &b[..], map, format);
+                    map_obj_with_name(name, data.unwrap_or(Value::Null))
+                };
+                (decode(&old), decode(mem))
+            });
+            self.data.data[name] = new_data;
+        }
+    }
+
+    fn read_all(&mut self, ctx: &mut Context<Self>) {
+        for (name, _) in self.version.symbols.clone() {
+            self.enqueue(name);
+        }
+        self.pump(ctx);
+    }
+
+    // a root that is queued or being read is not queued again
+    fn enqueue(&mut self, name: String) {
+        if !self.queue.contains(&name) && !self.reading.contains(&name) {
+            self.queue.push_back(name);
+        }
+    }
+
+    fn pump(&mut self, ctx: &mut Context<Self>) {
+        while self.reading.len() + self.stale < self.max_reads {
+            let name = match self.queue.pop_front() {
+                Some(name) => name,
+                None => return,
+            };
+            self.reading.insert(name.clone());
+            let generation = self.generation;
+            ctx.spawn(
+                wrap_future(handle_request(&self.client, &self.version, &name)).then(
+                    move |f, a: &mut Self, ctx: &mut Context<Self>| {
+                        // the data of a replaced version does not fit the memory anymore
+                        if a.generation != generation {
+                            a.stale -= 1;
+                        } else {
+                            a.reading.remove(&name);
+                            match f {
+                                Ok(Some(f)) => handle_future(&f, a, &name),
+                                Ok(None) => (),
+                                // handle_request logged why
+                                Err(()) => (),
+                            }
+                        }
+                        a.pump(ctx);
+                        actix::fut::ok(())
+                    },
+                ),
+            );
+        }
+    }
+
+    fn schedule(&self, ctx: &mut Context<Self>) {
+        for i in 0..self.version.polls.len() {
+            self.schedule_poll(i, ctx);
+        }
+    }
+
+    fn schedule_poll(&self, i: usize, ctx: &mut Context<Self>) {
+        let poll = &self.version.polls[i];
+        let jitter = if poll.jitter > 0 {
+            rand::thread_rng().gen_range(0, poll.jitter + 1)
+        } else {
+            0
+        };
+        let generation = self.generation;
+        ctx.run_later(
+            Duration::from_millis(poll.interval + jitter),
+            move |a, ctx| {
+                if a.generation != generation {
+                    return;
+                }
+                for root in a.version.polls[i].roots.clone() {
+                    a.enqueue(root);
+                }
+                a.pump(ctx);
+                a.schedule_poll(i, ctx);
+            },
+        );
+    }
+
+    fn notice(&self, notice: &str) {
+        let mut m = serde_json::Map::new();
+        m.insert("notice".to_string(), Value::String(notice.to_string()));
+        for c in &self.ws_clients {
+            c.do_send(AdsToWsClient(to_string(&m).unwrap()));
+        }
+    }
+
+    fn view(&mut self, name: &str, format: &ValueFormat) -> Result<Value, CodecError> {
+        if *format == ValueFormat::default() {
+            return Ok(self.data.data[name].clone());
+        }
+        if let Some(key) = self.version.search_index.get(&name.to_string()) {
+            let ty: &AdsType = &self.version.map.get(&*key).unwrap();
+            match self.data.by_str_mut(name) {
+                Some(mem) => ty
+                    .as_data_struct(&mut &mem[..], &self.version.map, format)
+                    .map_err(|e| e.field(name)),
+                None => Err(CodecError::unknown_type(name)),
+            }
+        } else {
+            Err(CodecError::unknown_type(name))
         }
     }
 }
@@ -96,28 +247,8 @@ impl Actor for AdsToWsMultiplexer {
                 ctx.add_stream(rx);
             }
         }));
-        ctx.spawn(wrap_future(handle_request(
-            &self.client,
-            &self.version,
-            &self.struct_map.by_str("ST_ADS_TO_BC"),
-            "ST_ADS_TO_BC",
-        )).map_err(|_, _: &mut Self, _: &mut Context<Self>| {
-            println!("error {} {}", file!(), line!())
-        })
-            .map(|f, a, _| {
-                handle_future(&f, a, "ST_ADS_TO_BC");
-            }));
-        ctx.spawn(wrap_future(handle_request(
-            &self.client,
-            &self.version,
-            &self.struct_map.by_str("ST_RETAIN_DATA"),
-            "ST_RETAIN_DATA",
-        )).map_err(|_, _: &mut Self, _: &mut Context<Self>| {
-            println!("error {} {}", file!(), line!())
-        })
-            .map(|f, a, _| {
-                handle_future(&f, a, "ST_RETAIN_DATA");
-            }));
+        self.read_all(ctx);
+        self.schedule(ctx);
         ctx.notify(HeartBeat);
     }
     fn stopped(&mut self, _: &mut Self::Context) {
@@ -129,15 +260,9 @@ impl Actor for AdsToWsMultiplexer {
 impl StreamHandler<AdsWriteReq, ()> for AdsToWsMultiplexer {
     fn handle(&mut self, item: AdsWriteReq, _: &mut Self::Context) {
         // SLAVE
-        let name = "ST_ADS_FROM_BC";
-        if let Some(key) = self.version.search_index.get(&name.to_string()) {
-            self.data.ST_ADS_FROM_BC[(item.index_offset as usize)..item.data.len()]
-                .clone_from_slice(&item.data[(item.index_offset as usize)..]);
-            let ty: &AdsType = &self.version.map.get(&*key).unwrap();
-            let new_data =
-                ty.as_data_struct(&mut self.data.ST_ADS_FROM_BC.as_slice(), &self.version.map);
-            self.data.data[name] =
-                handle_subscriptions(&self.subscription_map, &self.data.data, new_data, name);
+        let offset = item.index_offset as usize;
+        if let Some(name) = self.version.root(RootRole::FromPlc).map(|n| n.to_string()) {
+            self.update_memory(&name, offset, &item.data[offset..]);
         }
     }
 }
@@ -147,29 +272,78 @@ impl Handler<HeartBeat> for AdsToWsMultiplexer {
 
     fn handle(&mut self, _: HeartBeat, ctx: &mut Self::Context) -> Self::Result {
         let c = self.count;
-        let name = "ST_ADS_TO_BC";
-        self.count += 1;
-        LittleEndian::write_u32(&mut self.data.ST_ADS_TO_BC[16..20], c);
-        if let Some(key) = self.version.search_index.get(&name.to_string()) {
-            let ty: &AdsType = &self.version.map.get(&*key).unwrap();
-            let new_data =
-                ty.as_data_struct(&mut self.data.ST_ADS_TO_BC.as_slice(), &self.version.map);
-
-            self.data.data[name] =
-                handle_subscriptions(&self.subscription_map, &self.data.data, new_data, name);
+        self.count = self.count.wrapping_add(1);
+        let heartbeat = self.version.heartbeat.clone().and_then(|(name, offset, ty)| {
+            let symbol = self.version.symbols.get(&name).map(|s| s.clone());
+            symbol.map(|s| (name, offset, ty, s))
+        });
+        if let Some((name, offset, ty, symbol)) = heartbeat {
+            // the counter starts over where the field ends
+            let values = ty.counter_values(&self.version.map).unwrap_or(1 << 32);
+            let counter = Value::from((u128::from(c) % values) as u64);
+            let mut w = WriteContext::new(RangePolicy::PassThrough, ValueFormat::default());
+            let mut data = Vec::new();
+            match ty.to_writer(&counter, &mut data, &self.version.map, &mut w) {
+                Ok(()) => {
+                    self.update_memory(&name, offset, &data);
+                    self.client.do_send(AdsWriteReq {
+                        index_group: symbol.index_group,
+                        index_offset: symbol.index_offset + offset as u32,
+                        length: data.len() as u32,
+                        data,
+                    });
+                }
+                Err(e) => error!("heartbeat of {}: {}", name, e),
+            }
         }
-        let wr = AdsWriteReq {
-            index_group: self.struct_map.st_ads_to_bc.index_group,
-            index_offset: self.struct_map.st_ads_to_bc.index_offset + 16,
-            length: 4,
-            data: self.data.ST_ADS_TO_BC[16..20].to_vec(),
-        };
-        self.client.do_send(wr);
         ctx.notify_later(HeartBeat, Duration::new(5, 0));
         ()
     }
 }
 
+impl Handler<ClientOf> for AdsToWsMultiplexer {
+    type Result = MessageResult<ClientOf>;
+
+    fn handle(&mut self, _: ClientOf, _: &mut Self::Context) -> Self::Result {
+        MessageResult(self.client.clone())
+    }
+}
+
+impl Handler<Reload> for AdsToWsMultiplexer {
+    type Result = ();
+
+    fn handle(&mut self, msg: Reload, ctx: &mut Self::Context) -> Self::Result {
+        match msg {
+            Reload::Version(version, range_policy, max_reads) => {
+                self.range_policy = range_policy;
+                self.max_reads = max_reads;
+                if Arc::ptr_eq(&version, &self.version) {
+                    self.pump(ctx);
+                    return;
+                }
+                // the schemas of the clients may name fields that are gone now
+                self.subscription_map.clear();
+                self.data = AdsMemory::new(&version);
+                self.version = version;
+                self.generation += 1;
+                self.stale += self.reading.len();
+                self.reading.clear();
+                self.queue.clear();
+                self.read_all(ctx);
+                self.schedule(ctx);
+                self.notice("schema_changed");
+            }
+            Reload::Stop => {
+                self.notice("plc_removed");
+                for c in self.ws_clients.drain(..) {
+                    c.do_send(CloseWs);
+                }
+                ctx.stop();
+            }
+        }
+    }
+}
+
 impl Handler<WsToAdsClient> for AdsToWsMultiplexer {
     type Result = Box<ActorFuture<Item = String, Error = (), Actor = Self>>;
     fn handle(&mut self, msg: WsToAdsClient, _: &mut Self::Context) -> Self::Result {
@@ -186,55 +360,70 @@ impl Handler<WsToAdsClient> for AdsToWsMultiplexer {
                 }
                 Box::new(wrap_future(future::err(())))
             }
-            WsToAdsClient::Mutation(mutation) => {
-                let version = &self.version;
-                let mem = &mut self.data;
-                let client = &self.client;
-                let struct_map = &self.struct_map;
-                let subscription_map = &self.subscription_map;
+            WsToAdsClient::Mutation(mutation, format, range) => {
+                let range = range.unwrap_or(self.range_policy);
+                let mut errors = Vec::new();
                 if let Value::Object(obj) = mutation {
-                    let _: Vec<()> = obj
-                        .into_iter()
-                        .filter_map(|param: (String, Value)| {
-                            let (k, data) = param;
-                            if let Some(key_guard) = version.search_index.get(&k.trim().to_string())
-                            {
-                                let ty: &AdsType = &version.map.get(&*key_guard).unwrap();
-                                let data = merge(mem.data.get(k.trim()).unwrap().clone(), data);
-                                //let mut m = mem.by_str_mut(k.trim());
-                                let mut mem_data = Vec::new();
-                                let _ = ty.to_writer(&data, &mut mem_data, &version.map);
-                                mem.data[k.trim()] = handle_subscriptions(
-                                    subscription_map,
-                                    &mem.data[k.trim()],
-                                    data,
-                                    k.trim(),
-                                );
-                                let sm = struct_map.by_str(k.trim());
-                                let m = mem.by_str_mut(k.trim());
-                                let i = mem_data
-                                    .iter()
-                                    .zip(m.iter())
-                                    .rposition(|(v1, v2)| v1 != v2)
-                                    .unwrap_or(mem_data.len() - 1);
-                                m[..=i].clone_from_slice(&mem_data[..=i]);
-                                client.do_send(AdsWriteReq {
+                    let version = self.version.clone();
+                    for (k, data) in obj {
+                        let name = k.trim();
+                        let symbol = version.symbols.get(&name.to_string()).map(|s| s.clone());
+                        let key_guard = version.search_index.get(&name.to_string());
+                        let mem_data = self.data.by_str_mut(name).map(|m| m.to_vec());
+                        if let (Some(sm), Some(key_guard), Some(mut mem_data)) =
+                            (symbol, key_guard, mem_data)
+                        {
+                            let ty: &AdsType = &version.map.get(&*key_guard).unwrap();
+                            // null resets the whole symbol, with its own initial value if declared
+                            let patched = if data.is_null() {
+                                let default = sm.metadata.default.as_ref();
+                                ty.reset(&mut mem_data, &version.map, default)
+                                    .map(|ranges| (ranges, Vec::new()))
+                                    .map_err(|e| vec![e])
+                            } else {
+                                ty.patch(&data, &mut mem_data, &version.map, range, format)
+                            };
+                            let ranges = match patched {
+                                Ok((ranges, clamped)) => {
+                                    for c in clamped {
+                                        warn!("{}", c.field(name));
+                                    }
+                                    ranges
+                                }
+                                Err(rejected) => {
+                                    for e in rejected.into_iter().map(|e| e.field(name)) {
+                                        warn!("mutation rejected: {}", e);
+                                        errors.push(e.to_value());
+                                    }
+                                    continue;
+                                }
+                            };
+                            if let (Some(first), Some(last)) = (ranges.first(), ranges.last()) {
+                                let changed = &mem_data[first.start..last.end];
+                                self.update_memory(name, first.start, changed);
+                            }
+                            for r in ranges {
+                                self.client.do_send(AdsWriteReq {
                                     index_group: sm.index_group,
-                                    index_offset: sm.index_offset,
-                                    length: 1 + i as u32,
-                                    data: mem_data[..=i].to_vec(),
+                                    index_offset: sm.index_offset + r.start as u32,
+                                    length: r.len() as u32,
+                                    data: mem_data[r].to_vec(),
                                 });
                             }
-                            None
-                        })
-                        .collect();
+                        }
+                    }
+                }
+                if errors.is_empty() {
+                    Box::new(wrap_future(future::err(())))
+                } else {
+                    let mut m = serde_json::Map::new();
+                    m.insert("errors".to_string(), Value::Array(errors));
+                    Box::new(wrap_future(future::ok(to_string(&m).unwrap())))
                 }
-                Box::new(wrap_future(future::err(())))
             }
-            WsToAdsClient::Resolve(schema) => {
+            WsToAdsClient::Resolve(schema, format) => {
                 let client = self.client.clone();
                 let version = self.version.clone();
-                let sm = self.struct_map.clone();
                 if let Schema::Root(v) = schema {
                     Box::new(
                         wrap_future(
@@ -242,21 +431,23 @@ impl Handler<WsToAdsClient> for AdsToWsMultiplexer {
                                 let name = get_name(&schema_value);
                                 (
                                     future::ok(schema_value),
-                                    handle_request(
-                                        &client.clone(),
-                                        &version.clone(),
-                                        sm.by_str(&name),
-                                        &name,
-                                    ),
+                                    handle_request(&client, &version, &name),
                                 )
                             })).map_err(|_| println!("error {} {}", file!(), line!())),
-                        ).map(|item, actor, _| {
+                        ).map(move |item, actor, _| {
                             let schema_data = item
                                 .into_iter()
-                                .map(move |(schema_value, item)| {
+                                .filter_map(move |(schema_value, item)| {
                                     let name = get_name(&schema_value);
-                                    handle_future(&item, actor, &name);
-                                    schema_value.as_schema(&actor.data.data).unwrap()
+                                    if let Some(item) = item {
+                                        handle_future(&item, actor, &name);
+                                    }
+                                    let view = actor.view(&name, &format).unwrap_or_else(|e| {
+                                        error!("can not decode {}", e);
+                                        Value::Null
+                                    });
+                                    let view = map_obj_with_name(&name, view);
+                                    schema_value.as_schema(&view)
                                 })
                                 .collect();
                             serde_json::to_string(&merge_values(schema_data)).unwrap()
@@ -266,11 +457,15 @@ impl Handler<WsToAdsClient> for AdsToWsMultiplexer {
                     unreachable!()
                 }
             }
-            WsToAdsClient::Subscription(mut s, a) => {
-                let v = self.subscription_map.entry(a).or_insert_with(|| s.clone());
-                if v != &mut s {
-                    *v = merge_schemas(v.clone(), s)
+            WsToAdsClient::Subscription(s, a, format) => {
+                let v = self
+                    .subscription_map
+                    .entry(a)
+                    .or_insert_with(|| (s.clone(), format));
+                if v.0 != s {
+                    v.0 = merge_schemas(v.0.clone(), s)
                 }
+                v.1 = format;
                 Box::new(wrap_future(future::err(())))
             }
         }
@@ -284,57 +479,67 @@ fn get_name(s: &Schema) -> String {
         Schema::Root(_) => unreachable!(),
     }
 }
+// reads a root from the plc, roots without a symbol are only written by the plc
 fn handle_request(
     client: &Addr<Client>,
     version: &AdsVersion,
-    symbol: &Symbol,
     name: &str,
-) -> impl Future<Item = AdsReadRes, Error = ()> {
-    if let Some(key_guard) = version.search_index.get(&name.to_string()) {
-        let ty: &AdsType = &version.map.get(&*key_guard).unwrap();
-        client
-            .send(AdsReadReq {
-                index_group: symbol.index_group,
-                index_offset: symbol.index_offset,
-                length: ty.len(),
-            })
-            .map_err(|_| eprintln!("error"))
-            .map(|f| if let Ok(f) = f { f } else { unreachable!() })
-    } else {
-        unreachable!()
+) -> impl Future<Item = Option<AdsReadRes>, Error = ()> {
+    let symbol = version.symbols.get(&name.to_string());
+    let key_guard = version.search_index.get(&name.to_string());
+    match (symbol, key_guard) {
+        (Some(symbol), Some(key_guard)) => {
+            let ty: &AdsType = &version.map.get(&*key_guard).unwrap();
+            let name = name.to_string();
+            Either::A(
+                client
+                    .send(AdsReadReq {
+                        index_group: symbol.index_group,
+                        index_offset: symbol.index_offset,
+                        length: ty.len(),
+                    })
+                    .then(move |r| match r {
+                        Ok(Ok(f)) => Ok(Some(f)),
+                        Ok(Err(())) => {
+                            error!("the plc did not answer the read of {}", name);
+                            Err(())
+                        }
+                        Err(_) => {
+                            error!("the connection is gone, {} is not read", name);
+                            Err(())
+                        }
+                    }),
+            )
+        }
+        _ => Either::B(future::ok(None)),
     }
 }
 fn handle_future(item: &AdsReadRes, actor: &mut AdsToWsMultiplexer, name: &str) -> () {
-    let ndata = if let Some(key) = actor.version.search_index.get(&name.to_string()) {
-        let data: &mut [u8] = actor.data.by_str_mut(name);
-        let ty: &AdsType = &actor.version.map.get(&*key).unwrap();
-        data[..item.data.len()].clone_from_slice(&item.data[..]);
-        ty.as_data_struct(&mut &data[..], &actor.version.map)
-    } else {
-        unreachable!()
-    };
-    actor.data.data[name] =
-        handle_subscriptions(&actor.subscription_map, &actor.data.data, ndata, name);
+    actor.update_memory(name, 0, &item.data);
 }
-fn handle_subscriptions(
-    subscription_map: &HashMap<Addr<Ws>, Schema>,
-    old_data: &Value,
-    new_data: Value,
+fn handle_subscriptions<F>(
+    subscription_map: &HashMap<Addr<Ws>, (Schema, ValueFormat)>,
     name: &str,
-) -> Value {
-    let mut new_data = map_obj_with_name(name, new_data);
-    for (c, s) in subscription_map {
+    views: F,
+) where
+    F: Fn(&ValueFormat) -> (Value, Value),
+{
+    let mut cache: HashMap<ValueFormat, (Value, Value)> = HashMap::new();
+    for (c, (s, format)) in subscription_map {
         if let Schema::Root(v) = s {
-            for value in v {
-                let sv1 = value.as_schema(&new_data).unwrap();
-                let sv2 = value.as_schema(old_data).unwrap();
-                if sv1 != sv2 && !is_empty(&sv1) {
+            for value in v.iter().filter(|value| get_name(value) == name) {
+                let (old_data, new_data) = cache.entry(*format).or_insert_with(|| views(format));
+                // a subscribed field the view doesn't have is skipped
+                let sv1 = match value.as_schema(new_data) {
+                    Some(sv1) => sv1,
+                    None => continue,
+                };
+                if value.as_schema(old_data).as_ref() != Some(&sv1) && !is_empty(&sv1) {
                     c.do_send(AdsToWsClient(to_string(&sv1).unwrap()));
                 }
             }
         }
     }
-    new_data.as_object_mut().unwrap().remove(name).unwrap()
 }
 
 fn is_empty(v: &Value) -> bool {
@@ -0,0 +1,139 @@
+use bincode;
+use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
+use flate2::read::DeflateDecoder;
+use flate2::write::DeflateEncoder;
+use flate2::Compression;
+use serde_json::{self, Value};
+use settings::VersionSetting;
+use sha1::Sha1;
+use std::fs::{self, File};
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::path::{Path, PathBuf};
+use types::AdsVersion;
+
+const MAGIC: &[u8; 4] = b"ADSV";
+// raised whenever the serialized types change, older caches are rebuilt
+const FORMAT: u32 = 6;
+
+#[derive(Serialize)]
+struct CacheRef<'a> {
+    selection: String,
+    version: &'a AdsVersion,
+}
+
+#[derive(Deserialize)]
+struct Cache {
+    selection: String,
+    version: AdsVersion,
+}
+
+// the file name is the hash of the tpy and the selection, the same file under another path
+// hits too
+pub fn path(conf: &VersionSetting, dir: &str) -> io::Result<PathBuf> {
+    let mut bytes = Vec::new();
+    File::open(&conf.path)?.read_to_end(&mut bytes)?;
+    bytes.extend(selection(conf).as_bytes());
+    let hash = Sha1::from(&bytes).digest().to_string();
+    Ok(Path::new(dir).join(format!("{}.cache", hash)))
+}
+
+// everything but the path decides which symbols and roots the version holds
+fn selection(conf: &VersionSetting) -> String {
+    let mut v = serde_json::to_value(conf).unwrap_or(Value::Null);
+    if let Value::Object(ref mut o) = v {
+        o.remove("path");
+    }
+    v.to_string()
+}
+
+// a missing, outdated or foreign cache is no error, the version is read again
+pub fn load(conf: &VersionSetting, path: &Path) -> Option<AdsVersion> {
+    let mut r = BufReader::new(File::open(path).ok()?);
+    let mut magic = [0u8; 4];
+    r.read_exact(&mut magic).ok()?;
+    if &magic != MAGIC || r.read_u32::<LittleEndian>().ok()? != FORMAT {
+        return None;
+    }
+    let cache: Cache = match bincode::deserialize_from(DeflateDecoder::new(r)) {
+        Ok(c) => c,
+        Err(e) => {
+            warn!("can not read the cache {:?}: {}", path, e);
+            return None;
+        }
+    };
+    if cache.selection == selection(conf) {
+        Some(cache.version)
+    } else {
+        None
+    }
+}
+
+pub fn store(conf: &VersionSetting, version: &AdsVersion, path: &Path) -> io::Result<()> {
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    // written aside and renamed so a running server never sees half a file
+    let tmp = path.with_extension("tmp");
+    {
+        let mut w = BufWriter::new(File::create(&tmp)?);
+        w.write_all(MAGIC)?;
+        w.write_u32::<LittleEndian>(FORMAT)?;
+        let mut e = DeflateEncoder::new(w, Compression::default());
+        let cache = CacheRef {
+            selection: selection(conf),
+            version,
+        };
+        bincode::serialize_into(&mut e, &cache)
+            .map_err(|e| io::Error::new(io::ErrorKind::Other, e.to_string()))?;
+        e.finish()?.flush()?;
+    }
+    fs::rename(tmp, path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::read_version;
+    use super::*;
+    use std::collections::HashMap;
+    use std::env;
+    use std::process;
+
+    fn v14(symbol_names: Value) -> VersionSetting {
+        let path = concat!(env!("CARGO_MANIFEST_DIR"), "/versions/v14.tpy");
+        let conf = json!({
+            "path": path,
+            "symbol_names": symbol_names,
+            "roots": [
+                {"name": "ST_ADS_TO_BC", "role": "to_plc", "heartbeat": "udiRequestCounter"},
+                {"name": "ST_RETAIN_DATA", "role": "retain"}
+            ]
+        });
+        serde_json::from_value(conf).unwrap()
+    }
+
+    #[test]
+    fn a_stored_version_loads_alike() {
+        let dir = env::temp_dir().join(format!("ads_server_cache_{}", process::id()));
+        let conf = v14(json!([".RetainData", ".Master"]));
+        let path = path(&conf, dir.to_str().unwrap()).unwrap();
+        let version = read_version(&conf).unwrap();
+        store(&conf, &version, &path).unwrap();
+        let cached = load(&conf, &path).unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+        let map = |v: &AdsVersion| v.map.clone().into_iter().collect::<HashMap<_, _>>();
+        let symbols = |v: &AdsVersion| v.symbols.clone().into_iter().collect::<HashMap<_, _>>();
+        assert_eq!(map(&version), map(&cached));
+        assert_eq!(symbols(&version), symbols(&cached));
+        assert_eq!(version.roles, cached.roles);
+        assert_eq!(version.heartbeat, cached.heartbeat);
+        assert_eq!(version.polls, cached.polls);
+    }
+
+    #[test]
+    fn selections_of_one_tpy_are_cached_apart() {
+        let master = v14(json!([".RetainData", ".Master"]));
+        let slave = v14(json!([".RetainData", ".Slave"]));
+        assert_ne!(path(&master, "cache").unwrap(), path(&slave, "cache").unwrap());
+        assert_eq!(path(&master, "cache").unwrap(), path(&master.clone(), "cache").unwrap());
+    }
+}
@@ -1,25 +1,32 @@
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use types::{AdsPlcType, AdsType};
 
-pub fn build_dependencies<'b>(key: &'b str, v: &mut Vec<String>, map: &HashMap<String, AdsType>) {
+// a type that is already known is not walked again
+pub fn build_dependencies<'b>(
+    key: &'b str,
+    v: &mut HashSet<String>,
+    map: &HashMap<String, AdsType>,
+) {
     match map.get(key) {
         Some(AdsType::Struct { properties, .. }) => {
             let _: Vec<_> = properties
                 .iter()
                 .map(|f| {
                     if let AdsPlcType::Other { ref reference, .. } = f.ty {
-                        v.push(reference.clone());
-                        build_dependencies(reference, v, map)
+                        if v.insert(reference.clone()) {
+                            build_dependencies(reference, v, map)
+                        }
                     }
                 })
                 .collect();
         }
         Some(AdsType::Enum { .. }) => {
-            v.push(key.to_string());
+            v.insert(key.to_string());
         }
         Some(AdsType::Array { ty, .. }) => if let AdsPlcType::Other { ref reference, .. } = ty {
-            v.push(reference.clone());
-            build_dependencies(reference, v, map);
+            if v.insert(reference.clone()) {
+                build_dependencies(reference, v, map);
+            }
         },
         _ => {}
     }
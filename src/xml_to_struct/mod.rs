@@ -1,68 +1,323 @@
+mod cache;
 mod helper;
+mod select;
+mod stream;
+mod tmc;
 
 use chashmap::CHashMap;
 use quickxml_to_serde;
 use serde_json::Value;
-use settings::VersionSetting;
+use settings::{ConfigError, VersionSetting};
 use std::{
-    collections::HashMap,
+    collections::{BTreeMap, HashMap, HashSet},
+    fmt,
     fs::File,
     io::{BufRead, BufReader},
+    path::{Path, PathBuf},
+    time::{Duration, Instant},
 };
 
 use self::helper::build_dependencies;
-use types::helper::{number_from_value, type_from_value};
-use types::{AdsPlcType, AdsType, AdsVersion, Name, Symbol};
+use self::select::Selection;
+use types::helper::{fit_string, number_from_value, type_from_value};
+use types::{
+    AdsPlcType, AdsStructProperties, AdsType, AdsVersion, Metadata, Name, Poll, RootRole, Symbol,
+};
+
+fn unreadable<E: fmt::Display>(conf: &VersionSetting, e: E) -> Vec<ConfigError> {
+    vec![ConfigError::Unreadable {
+        file: conf.path.clone(),
+        error: e.to_string(),
+    }]
+}
+
+fn xml_to_json<R: BufRead>(r: R, conf: &VersionSetting) -> Result<Value, Vec<ConfigError>> {
+    let e = quickxml_to_serde::get_root(r).map_err(|e| unreadable(conf, e))?;
+    Ok(quickxml_to_serde::xml_to_map(&e))
+}
+
+// program variables are named PRG.var, globals start with a dot
+fn program_of(name: &str) -> Option<&str> {
+    match name.find('.') {
+        Some(i) if i > 0 => Some(&name[..i]),
+        _ => None,
+    }
+}
+
+// a program is read at once, so its variables may not lie further apart than this many bytes
+const PROGRAM_GAP: u32 = 1024;
+
+fn contiguous(vars: &mut [(Symbol, u32)]) -> bool {
+    vars.sort_by_key(|(s, _)| (s.index_group, s.index_offset));
+    vars.windows(2).all(|w| {
+        let ((a, a_bits), (b, _)) = (&w[0], &w[1]);
+        let end = a.index_offset + a_bits / 8;
+        a.index_group == b.index_group && b.index_offset <= end + PROGRAM_GAP
+    })
+}
+
+fn open(conf: &VersionSetting) -> Result<BufReader<File>, Vec<ConfigError>> {
+    File::open(&conf.path)
+        .map(BufReader::new)
+        .map_err(|e| unreadable(conf, e))
+}
+
+// tpy files of TwinCAT 2 start with PlcProjectInfo, tmc files of TwinCAT 3 with TcModuleClass
+fn is_tmc(conf: &VersionSetting) -> bool {
+    let extension = Path::new(&conf.path)
+        .extension()
+        .and_then(|e| e.to_str())
+        .map(|e| e.to_lowercase());
+    match extension.as_ref().map(|e| e.as_str()) {
+        Some("tmc") => true,
+        Some("tpy") => false,
+        _ => open(conf)
+            .ok()
+            .and_then(stream::root_name)
+            .map_or(false, |r| r == "TcModuleClass"),
+    }
+}
+
+// tpy files are streamed, tmc files are still read as a whole
+pub fn read_version(conf: &VersionSetting) -> Result<AdsVersion, Vec<ConfigError>> {
+    let (all_symbols, map) = if is_tmc(conf) {
+        tmc::read_tmc(&xml_to_json(open(conf)?, conf)?)
+    } else {
+        stream::read_tpy(open(conf)?, conf)?
+    };
+    build_version(conf, all_symbols, map)
+}
+
+// the cache in dir is used while the tpy and the selection stay the same
+pub fn read_version_cached(
+    conf: &VersionSetting,
+    dir: &str,
+) -> Result<AdsVersion, Vec<ConfigError>> {
+    let path = match cache::path(conf, dir) {
+        Ok(path) => path,
+        Err(_) => return read_version(conf),
+    };
+    if let Some(version) = cache::load(conf, &path) {
+        debug!("{} read from {:?}", conf.path, path);
+        return Ok(version);
+    }
+    let version = read_version(conf)?;
+    if let Err(e) = cache::store(conf, &version, &path) {
+        warn!("can not write the cache {:?}: {}", path, e);
+    }
+    Ok(version)
+}
+
+// reads the version again and replaces its cache
+pub fn build_cache(conf: &VersionSetting, dir: &str) -> Result<PathBuf, String> {
+    let path = cache::path(conf, dir).map_err(|e| e.to_string())?;
+    let version = read_version(conf).map_err(|errors| {
+        let errors: Vec<String> = errors.iter().map(|e| e.to_string()).collect();
+        errors.join(", ")
+    })?;
+    cache::store(conf, &version, &path).map_err(|e| e.to_string())?;
+    Ok(path)
+}
+
+fn average<F: FnMut()>(runs: u32, mut f: F) -> Duration {
+    let start = Instant::now();
+    for _ in 0..runs {
+        f();
+    }
+    start.elapsed() / runs
+}
 
-fn xml_to_json<R: BufRead>(r: R) -> Value {
-    let e = quickxml_to_serde::get_root(r).unwrap();
-    quickxml_to_serde::xml_to_map(&e)
+// reads a tpy through the document tree and through the stream parser, the
+// results have to be equal; returns false on any difference
+pub fn compare_parsers(conf: &VersionSetting, runs: u32) -> bool {
+    if is_tmc(conf) {
+        println!("{}: tmc files are only read as a whole", conf.path);
+        return true;
+    }
+    let tree = match open(conf).and_then(|r| xml_to_json(r, conf)) {
+        Ok(tree) => read_tpy(&tree),
+        Err(errors) => return report(errors),
+    };
+    let tree = match reachable(conf, &tree.0, tree.1) {
+        Ok(map) => (tree.0, map),
+        Err(errors) => return report(errors),
+    };
+    let streamed = match open(conf).and_then(|r| stream::read_tpy(r, conf)) {
+        Ok(streamed) => streamed,
+        Err(errors) => return report(errors),
+    };
+    let mut equal = tree.0 == streamed.0;
+    if !equal {
+        println!("{}: the symbols differ", conf.path);
+    }
+    let mut keys: Vec<&String> = tree.1.keys().chain(streamed.1.keys()).collect();
+    keys.sort();
+    keys.dedup();
+    for k in keys {
+        if tree.1.get(k) != streamed.1.get(k) {
+            println!("{}: the type {} differs", conf.path, k);
+            equal = false;
+        }
+    }
+    let tree_time = average(runs, || {
+        let _ = open(conf).and_then(|r| xml_to_json(r, conf)).map(|v| read_tpy(&v));
+    });
+    let stream_time = average(runs, || {
+        let _ = open(conf).and_then(|r| stream::read_tpy(r, conf));
+    });
+    println!(
+        "{}: {} symbols, {} types, tree {:?}, stream {:?} per read",
+        conf.path,
+        tree.0.len(),
+        tree.1.len(),
+        tree_time,
+        stream_time
+    );
+    equal
+}
+
+fn report(errors: Vec<ConfigError>) -> bool {
+    for e in errors {
+        println!("{}", e);
+    }
+    false
+}
+
+// the types the roots, the wanted symbols and the identity symbol refer to
+fn reachable(
+    conf: &VersionSetting,
+    symbols: &[(Symbol, u32)],
+    map: HashMap<String, AdsType>,
+) -> Result<HashMap<String, AdsType>, Vec<ConfigError>> {
+    let selection = Selection::new(conf)?;
+    let identity = conf.identity.symbol.as_ref().map(|p| p.trim());
+    let mut dep = HashSet::new();
+    for (k, v) in &map {
+        if let AdsType::Struct { name, .. } = v {
+            if conf.roots.iter().any(|r| r.name == *name) {
+                dep.insert(k.clone());
+                build_dependencies(k, &mut dep, &map);
+            }
+        }
+    }
+    for (s, _) in symbols {
+        let name = s.name.text.as_str();
+        let identifies =
+            identity.map_or(false, |p| p == name || p.starts_with(&format!("{}.", name)));
+        if let AdsPlcType::Other { ref reference, .. } = s.ty {
+            if identifies || selection.wants(name) {
+                dep.insert(reference.clone());
+                build_dependencies(reference, &mut dep, &map);
+            }
+        }
+    }
+    Ok(map.into_iter().filter(|(k, _)| dep.contains(k)).collect())
 }
 
-pub fn read_tpy(conf: &VersionSetting) -> AdsVersion {
-    let f = BufReader::new(File::open(&conf.path).unwrap());
-    let e = xml_to_json(f);
-    let symbols: CHashMap<String, Symbol> = (&e["PlcProjectInfo"]["Symbols"]["Symbol"])
+fn read_tpy(e: &Value) -> (Vec<(Symbol, u32)>, HashMap<String, AdsType>) {
+    let all_symbols = (&e["PlcProjectInfo"]["Symbols"]["Symbol"])
         .as_array()
         .unwrap()
         .iter()
-        .map(|s| {
-            let index_group = number_from_value(&s["IGroup"]);
-            let index_offset = number_from_value(&s["IOffset"]);
-            let name: Name = (&s["Name"]).into();
-            let ty = type_from_value(&s["Type"], None);
-            Symbol {
-                index_group,
-                index_offset,
-                name,
-                ty,
-            }
-        })
-        .filter(|f| conf.symbol_names.contains(&f.name.text))
-        .filter_map(|f| match f.ty.clone() {
-            AdsPlcType::Other { ref reference, .. } => Some((reference.to_string(), f)),
-            _ => None,
-        })
+        .map(tpy_symbol)
         .collect();
-    let data_types = e["PlcProjectInfo"]["DataTypes"]["DataType"]
+    let map = e["PlcProjectInfo"]["DataTypes"]["DataType"]
         .as_array()
-        .unwrap();
-    let search_vec = vec!["ST_ADS_FROM_BC", "ST_ADS_TO_BC", "ST_RETAIN_DATA"];
-    let search_index = CHashMap::with_capacity(search_vec.len());
-    let map: HashMap<String, AdsType> = data_types
+        .unwrap()
         .iter()
-        .filter_map(|f| {
-            let name: Name = (&f["Name"]).into();
-            match name.decoration {
-                Some(d) => match AdsType::from_value(f) {
-                    Some(v) => Some((d, v)),
-                    None => None,
-                },
-                None => None,
-            }
-        })
+        .filter_map(tpy_data_type)
         .collect();
-    let mut dep = Vec::new();
+    (all_symbols, map)
+}
+
+fn tpy_symbol(s: &Value) -> (Symbol, u32) {
+    let index_group = number_from_value(&s["IGroup"]);
+    let index_offset = number_from_value(&s["IOffset"]);
+    let bit_size = number_from_value(&s["BitSize"]);
+    let name: Name = (&s["Name"]).into();
+    let ty = if s["Type"].get("@Pointer").is_some() {
+        AdsPlcType::Pointer(bit_size as usize / 8)
+    } else {
+        fit_string(type_from_value(&s["Type"], None), bit_size)
+    };
+    let symbol = Symbol {
+        index_group,
+        index_offset,
+        name,
+        ty,
+        metadata: Metadata::from_value(s),
+    };
+    (symbol, bit_size)
+}
+
+// types are keyed by their decoration, the types refer to each other by it
+fn tpy_data_type(f: &Value) -> Option<(String, AdsType)> {
+    let name: Name = (&f["Name"]).into();
+    match name.decoration {
+        Some(d) => match AdsType::from_value(f) {
+            Some(v) => Some((d, v)),
+            None => None,
+        },
+        None => None,
+    }
+}
+
+// the identity may name a field inside a symbol, its offset is added to the symbol's
+fn field_symbol(
+    symbols: &[(Symbol, u32)],
+    path: &str,
+    map: &HashMap<String, AdsType>,
+) -> Option<(Symbol, u32)> {
+    let path = path.trim();
+    let (symbol, bit_size) = symbols
+        .iter()
+        .filter(|(s, _)| {
+            let name = s.name.text.as_str();
+            path == name || path.starts_with(&format!("{}.", name))
+        })
+        .max_by_key(|(s, _)| s.name.text.len())?;
+    let mut field = symbol.clone();
+    let mut len = bit_size / 8;
+    for name in path[symbol.name.text.len()..].split('.').filter(|n| !n.is_empty()) {
+        let property = match field.ty {
+            AdsPlcType::Other { ref reference, .. } => match map.get(reference.trim()) {
+                Some(AdsType::Struct { properties, .. }) => {
+                    properties.iter().find(|p| p.name.trim() == name)?.clone()
+                }
+                _ => return None,
+            },
+            _ => return None,
+        };
+        field.index_offset += property.bit_offs / 8;
+        len = match property.ty {
+            AdsPlcType::Other { ref reference, .. } => map.get(reference.trim())?.len(),
+            ref ty => AdsType::Primitive(ty.clone()).len(),
+        };
+        field.ty = property.ty;
+        field.metadata = property.metadata;
+    }
+    field.name.text = path.to_string();
+    Some((field, len))
+}
+
+fn build_version(
+    conf: &VersionSetting,
+    all_symbols: Vec<(Symbol, u32)>,
+    mut map: HashMap<String, AdsType>,
+) -> Result<AdsVersion, Vec<ConfigError>> {
+    // found among all symbols, it does not have to be selected
+    let identity_symbol = conf
+        .identity
+        .symbol
+        .as_ref()
+        .map(|path| (path, field_symbol(&all_symbols, path, &map)));
+    let all_symbols = Selection::new(conf)?.select(all_symbols);
+    let search_vec: Vec<&str> = conf.roots.iter().map(|r| r.name.as_str()).collect();
+    let search_index = CHashMap::with_capacity(search_vec.len());
+    let symbols = CHashMap::new();
+    // a symbol is exposed under its type name if that is a root
+    let mut symbol_roots = HashMap::new();
+    let mut dep = HashSet::new();
     let _: Vec<_> = map
         .iter()
         .filter_map(|(k, v)| match v {
@@ -76,18 +331,339 @@ pub fn read_tpy(conf: &VersionSetting) -> AdsVersion {
         })
         .map(|f| f.as_ref())
         .map(|f: &str| {
-            dep.push(f.to_string());
+            dep.insert(f.to_string());
             build_dependencies(f, &mut dep, &map);
         })
         .collect();
+    // the variables of a program are exposed as one struct spanning all of them, unless
+    // they are spread over memory, then each of them is a root of its own
+    let mut programs: BTreeMap<String, Vec<(Symbol, u32)>> = BTreeMap::new();
+    let mut singles = Vec::new();
+    for (s, bit_size) in all_symbols {
+        match program_of(&s.name.text) {
+            Some(p) => programs
+                .entry(p.to_string())
+                .or_insert_with(Vec::new)
+                .push((s, bit_size)),
+            None => singles.push((s, bit_size)),
+        }
+    }
+    let programs: Vec<(String, Vec<(Symbol, u32)>)> = programs
+        .into_iter()
+        .filter_map(|(name, mut vars)| {
+            if contiguous(&mut vars) {
+                Some((name, vars))
+            } else {
+                info!("the variables of {} are read one by one", name);
+                singles.extend(vars);
+                None
+            }
+        })
+        .collect();
+    for (s, _) in singles {
+        let (root, key) = match s.ty {
+            AdsPlcType::Other {
+                ref name,
+                ref reference,
+            } => {
+                let root = if search_vec.contains(&name.trim()) {
+                    name.trim().to_string()
+                } else {
+                    s.name.text.trim_left_matches('.').to_string()
+                };
+                (root, reference.to_string())
+            }
+            ref ty => {
+                let root = s.name.text.trim_left_matches('.').to_string();
+                map.insert(root.clone(), AdsType::Primitive(ty.clone()));
+                (root.clone(), root)
+            }
+        };
+        dep.insert(key.clone());
+        build_dependencies(&key, &mut dep, &map);
+        search_index.insert(root.clone(), key);
+        symbol_roots.insert(s.name.text.clone(), root.clone());
+        symbols.insert(root, s);
+    }
+    for (name, vars) in programs {
+        if vars.is_empty() {
+            continue;
+        }
+        let index_group = vars[0].0.index_group;
+        let base = vars[0].0.index_offset;
+        let end = vars
+            .iter()
+            .map(|(s, bit_size)| s.index_offset + bit_size / 8)
+            .fold(base, u32::max);
+        let properties = vars
+            .into_iter()
+            .map(|(s, _)| AdsStructProperties {
+                name: s.name.text[name.len() + 1..].to_string(),
+                bit_offs: (s.index_offset - base) * 8,
+                ty: s.ty,
+                metadata: s.metadata,
+            })
+            .collect();
+        map.insert(
+            name.clone(),
+            AdsType::Struct {
+                name: name.clone(),
+                properties,
+                bit_size: (end - base) * 8,
+            },
+        );
+        dep.insert(name.clone());
+        build_dependencies(&name, &mut dep, &map);
+        search_index.insert(name.clone(), name.clone());
+        symbols.insert(
+            name.clone(),
+            Symbol {
+                index_group,
+                index_offset: base,
+                name: Name {
+                    text: name.clone(),
+                    decoration: None,
+                },
+                ty: AdsPlcType::Other {
+                    name: name.clone(),
+                    reference: name,
+                },
+                metadata: Metadata::default(),
+            },
+        );
+    }
+    let mut errors = Vec::new();
+    let mut roles = HashMap::new();
+    let mut heartbeat = None;
+    let mut counter = None;
+    for r in &conf.roots {
+        let root = match symbol_roots.get(&r.name) {
+            Some(root) => root.to_string(),
+            None => r.name.trim_left_matches('.').to_string(),
+        };
+        let key = match search_index.get(&root) {
+            Some(key) => key.to_string(),
+            None => {
+                errors.push(ConfigError::MissingRoot {
+                    version: conf.path.clone(),
+                    root: r.name.clone(),
+                });
+                continue;
+            }
+        };
+        if r.role != RootRole::Data {
+            roles.insert(root.clone(), r.role);
+        }
+        if let (RootRole::ToPlc, Some(field)) = (r.role, &r.heartbeat) {
+            let property = match map.get(&key) {
+                Some(AdsType::Struct { properties, .. }) => {
+                    properties.iter().find(|p| p.name.trim() == field)
+                }
+                _ => None,
+            };
+            match property {
+                Some(p) => {
+                    heartbeat = Some((root, (p.bit_offs / 8) as usize, p.ty.clone()));
+                    counter = Some((r.name.clone(), field.clone()));
+                }
+                None => errors.push(ConfigError::MissingField {
+                    version: conf.path.clone(),
+                    root: r.name.clone(),
+                    field: field.clone(),
+                }),
+            }
+        }
+    }
+    // poll patterns name roots or the symbols behind them, only roots with a symbol are read
+    let mut polls = Vec::new();
+    for p in &conf.poll {
+        let mut roots = Vec::new();
+        for pattern in &p.symbols {
+            let re = match select::compile(pattern) {
+                Ok(re) => re,
+                Err(error) => {
+                    errors.push(ConfigError::InvalidPattern {
+                        version: conf.path.clone(),
+                        pattern: pattern.clone(),
+                        error,
+                    });
+                    continue;
+                }
+            };
+            let mut matched: Vec<String> = symbols
+                .clone()
+                .into_iter()
+                .filter(|(root, s)| re.is_match(root) || re.is_match(s.name.text.trim()))
+                .map(|(root, _)| root)
+                .filter(|root| !roots.contains(root))
+                .collect();
+            if matched.is_empty() {
+                errors.push(ConfigError::NothingToPoll {
+                    version: conf.path.clone(),
+                    pattern: pattern.clone(),
+                });
+            }
+            matched.sort();
+            roots.extend(matched);
+        }
+        polls.push(Poll {
+            interval: p.interval,
+            jitter: p.jitter,
+            roots,
+        });
+    }
+    let identity_symbol = match identity_symbol {
+        Some((_, Some((symbol, len)))) => {
+            if let AdsPlcType::Other { ref reference, .. } = symbol.ty {
+                dep.insert(reference.to_string());
+                build_dependencies(reference, &mut dep, &map);
+            }
+            Some((symbol, len))
+        }
+        Some((path, None)) => {
+            errors.push(ConfigError::MissingSymbol {
+                version: conf.path.clone(),
+                symbol: path.clone(),
+            });
+            None
+        }
+        None => None,
+    };
+    if !errors.is_empty() {
+        return Err(errors);
+    }
     let fmap: CHashMap<String, AdsType> = map
         .into_iter()
         .filter_map(|(k, v)| if dep.contains(&k) { Some((k, v)) } else { None })
         .collect();
+    if let (Some((_, _, ty)), Some((root, field))) = (&heartbeat, counter) {
+        if ty.counter_values(&fmap).is_none() {
+            return Err(vec![ConfigError::NoCounter {
+                version: conf.path.clone(),
+                root,
+                field,
+            }]);
+        }
+    }
 
-    AdsVersion {
+    Ok(AdsVersion {
         map: fmap,
         search_index,
         symbols,
+        roles,
+        heartbeat,
+        identity_symbol,
+        polls,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json;
+    use test::Bencher;
+
+    fn v14(all_symbols: bool) -> VersionSetting {
+        let path = concat!(env!("CARGO_MANIFEST_DIR"), "/versions/v14.tpy");
+        let conf = json!({
+            "path": path,
+            "symbol_names": [".RetainData", ".Master", ".Slave"],
+            "all_symbols": all_symbols,
+            "size_limit": 1u64 << 30,
+            "roots": [
+                {"name": "ST_ADS_FROM_BC", "role": "from_plc"},
+                {"name": "ST_ADS_TO_BC", "role": "to_plc", "heartbeat": "udiRequestCounter"},
+                {"name": "ST_RETAIN_DATA", "role": "retain"}
+            ]
+        });
+        serde_json::from_value(conf).unwrap()
+    }
+
+    fn sorted<V: Clone>(m: &CHashMap<String, V>) -> Vec<(String, V)> {
+        let mut v: Vec<(String, V)> = m.clone().into_iter().collect();
+        v.sort_by(|a, b| a.0.cmp(&b.0));
+        v
+    }
+
+    #[test]
+    fn both_parsers_read_v14_alike() {
+        for all_symbols in &[false, true] {
+            let conf = v14(*all_symbols);
+            let (symbols, map) = read_tpy(&xml_to_json(open(&conf).unwrap(), &conf).unwrap());
+            let types = map.len();
+            let kept = reachable(&conf, &symbols, map.clone()).unwrap().len();
+            let tree = build_version(&conf, symbols, map).unwrap();
+            let (symbols, map) = stream::read_tpy(open(&conf).unwrap(), &conf).unwrap();
+            assert!(kept < types);
+            assert_eq!(map.len(), kept, "only the types nothing refers to are dropped");
+            let streamed = build_version(&conf, symbols, map).unwrap();
+            assert!(!streamed.map.is_empty());
+            assert_eq!(sorted(&tree.map), sorted(&streamed.map));
+            assert_eq!(sorted(&tree.symbols), sorted(&streamed.symbols));
+            assert_eq!(sorted(&tree.search_index), sorted(&streamed.search_index));
+            assert_eq!(tree.roles, streamed.roles);
+            assert_eq!(tree.heartbeat, streamed.heartbeat);
+            let (_, _, counter) = streamed.heartbeat.unwrap();
+            assert_eq!(counter, AdsPlcType::UDInt(None));
+            assert_eq!(tree.identity_symbol, streamed.identity_symbol);
+            assert_eq!(tree.polls, streamed.polls);
+        }
+    }
+
+    #[test]
+    fn broken_xml_is_an_error() {
+        let conf = v14(false);
+        let xml = "<PlcProjectInfo><DataTypes><DataType><Name>A</Nme></DataType>";
+        assert!(stream::read_tpy(xml.as_bytes(), &conf).is_err());
+    }
+
+    #[bench]
+    fn read_v14_as_tree(b: &mut Bencher) {
+        let conf = v14(false);
+        b.iter(|| read_tpy(&xml_to_json(open(&conf).unwrap(), &conf).unwrap()));
+    }
+
+    #[bench]
+    fn read_v14_streamed(b: &mut Bencher) {
+        let conf = v14(false);
+        b.iter(|| stream::read_tpy(open(&conf).unwrap(), &conf).unwrap());
+    }
+
+    fn var(name: &str, index_group: u32, index_offset: u32) -> (Symbol, u32) {
+        let symbol = Symbol {
+            index_group,
+            index_offset,
+            name: Name {
+                text: name.to_string(),
+                decoration: None,
+            },
+            ty: AdsPlcType::Int(None),
+            metadata: Metadata::default(),
+        };
+        (symbol, 16)
+    }
+
+    #[test]
+    fn sparse_programs_are_read_by_variable() {
+        let conf = json!({"path": "v.tpy", "all_symbols": true, "roots": [{"name": "MAIN"}]});
+        let conf: VersionSetting = serde_json::from_value(conf).unwrap();
+        let symbols = vec![
+            var("MAIN.nA", 0x4040, 10),
+            var("MAIN.nB", 0x4040, 14),
+            var("FAR.nX", 0x4040, 0),
+            var("FAR.nY", 0x4040, 100_000),
+            var("SPLIT.nX", 0x4040, 0),
+            var("SPLIT.nY", 0xF020, 2),
+        ];
+        let version = build_version(&conf, symbols, HashMap::new()).unwrap();
+        let root = |name: &str| version.symbols.get(&name.to_string()).map(|s| s.clone());
+        let main = "MAIN".to_string();
+        assert_eq!(root("MAIN").map(|s| s.index_offset), Some(10));
+        assert_eq!(version.map.get(&main).map(|t| t.len()), Some(6));
+        for name in &["FAR.nX", "FAR.nY", "SPLIT.nX", "SPLIT.nY"] {
+            assert!(root(name).is_some(), "{} is no root", name);
+        }
+        assert!(root("FAR").is_none());
+        assert!(root("SPLIT").is_none());
     }
 }
@@ -0,0 +1,118 @@
+use regex::{escape, Regex};
+use settings::{ConfigError, VersionSetting};
+use types::Symbol;
+
+use super::program_of;
+
+// which symbols of a version are exposed
+pub struct Selection<'a> {
+    conf: &'a VersionSetting,
+    include: Vec<Regex>,
+    exclude: Vec<Regex>,
+}
+
+// a pattern between slashes is a regex, anything else a glob with * and ?
+pub fn compile(pattern: &str) -> Result<Regex, String> {
+    let p = pattern.trim();
+    let re = if p.len() > 1 && p.starts_with('/') && p.ends_with('/') {
+        p[1..p.len() - 1].to_string()
+    } else {
+        let glob: Vec<String> = p
+            .split('*')
+            .map(|part| {
+                part.split('?')
+                    .map(|s| escape(s))
+                    .collect::<Vec<_>>()
+                    .join(".")
+            })
+            .collect();
+        format!("^{}$", glob.join(".*"))
+    };
+    Regex::new(&re).map_err(|e| e.to_string())
+}
+
+impl<'a> Selection<'a> {
+    pub fn new(conf: &'a VersionSetting) -> Result<Self, Vec<ConfigError>> {
+        let mut errors = Vec::new();
+        let mut compile_all = |patterns: &[String]| {
+            patterns
+                .iter()
+                .filter_map(|p| match compile(p) {
+                    Ok(re) => Some(re),
+                    Err(error) => {
+                        errors.push(ConfigError::InvalidPattern {
+                            version: conf.path.clone(),
+                            pattern: p.clone(),
+                            error,
+                        });
+                        None
+                    }
+                })
+                .collect()
+        };
+        let include = compile_all(&conf.include);
+        let exclude = compile_all(&conf.exclude);
+        if !errors.is_empty() {
+            return Err(errors);
+        }
+        Ok(Selection {
+            conf,
+            include,
+            exclude,
+        })
+    }
+
+    // named in symbol_names or as a root, always exposed
+    fn listed(&self, name: &str) -> bool {
+        let named = |n: &str| {
+            self.conf.symbol_names.iter().any(|s| s == n)
+                || self.conf.roots.iter().any(|r| r.name == n)
+        };
+        named(name) || program_of(name).map_or(false, named)
+    }
+
+    fn matches(&self, name: &str) -> bool {
+        let matches = |re: &Regex| {
+            re.is_match(name) || program_of(name).map_or(false, |p| re.is_match(p))
+        };
+        (self.conf.all_symbols || self.include.iter().any(&matches))
+            && !self.exclude.iter().any(&matches)
+    }
+
+    // listed or matched, regardless of the size limit
+    pub fn wants(&self, name: &str) -> bool {
+        self.listed(name) || self.matches(name)
+    }
+
+    // symbols found by patterns are taken in file order until the size limit is reached
+    pub fn select(&self, symbols: Vec<(Symbol, u32)>) -> Vec<(Symbol, u32)> {
+        let mut size = 0;
+        let mut skipped = 0;
+        let selected = symbols
+            .into_iter()
+            .filter(|(s, bit_size)| {
+                let name = &s.name.text;
+                if self.listed(name) {
+                    return true;
+                }
+                if !self.matches(name) {
+                    return false;
+                }
+                let bytes = u64::from(*bit_size / 8);
+                if size + bytes > self.conf.size_limit {
+                    skipped += 1;
+                    return false;
+                }
+                size += bytes;
+                true
+            })
+            .collect();
+        if skipped > 0 {
+            warn!(
+                "{}: {} symbols skipped, the size limit of {} bytes is reached",
+                self.conf.path, skipped, self.conf.size_limit
+            );
+        }
+        selected
+    }
+}
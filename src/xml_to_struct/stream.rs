@@ -0,0 +1,189 @@
+use quick_xml::events::{BytesStart, Event};
+use quick_xml::{self, Reader};
+use serde_json::{Map, Number, Value};
+use settings::{ConfigError, VersionSetting};
+use std::collections::HashMap;
+use std::io::BufRead;
+use std::str;
+use types::{AdsType, Symbol};
+
+use super::{reachable, tpy_data_type, tpy_symbol};
+
+// an element of a DataType or Symbol while it is read
+struct Node {
+    name: String,
+    attributes: Vec<(String, String)>,
+    text: String,
+    children: Map<String, Value>,
+}
+
+impl Node {
+    fn new<R: BufRead>(reader: &Reader<R>, e: &BytesStart) -> Self {
+        let attributes = e
+            .attributes()
+            .filter_map(|a| a.ok())
+            .filter_map(|a| {
+                let key = str::from_utf8(a.key).ok()?.to_string();
+                let value = a.unescape_and_decode_value(reader).ok()?;
+                Some((key, value))
+            })
+            .filter(|(k, _)| k != "xmlns" && !k.starts_with("xmlns:"))
+            .collect();
+        Node {
+            name: local_name(e.name()),
+            attributes,
+            text: String::new(),
+            children: Map::new(),
+        }
+    }
+
+    // repeated children become arrays, like the tree parser does it
+    fn push(&mut self, name: String, value: Value) {
+        let repeated = match self.children.get_mut(&name) {
+            Some(Value::Array(ref mut a)) => {
+                a.push(value);
+                return;
+            }
+            Some(v) => v.take(),
+            None => {
+                self.children.insert(name, value);
+                return;
+            }
+        };
+        self.children.insert(name, Value::Array(vec![repeated, value]));
+    }
+
+    // elements with text lose their children, as in quickxml_to_serde
+    fn into_value(self) -> Value {
+        let has_attributes = !self.attributes.is_empty();
+        let attributes = self
+            .attributes
+            .into_iter()
+            .map(|(k, v)| (format!("@{}", k), parse_text(&v)));
+        if self.text.trim() != "" {
+            if !has_attributes {
+                parse_text(&self.text)
+            } else {
+                let text = ("#text".to_string(), parse_text(&self.text));
+                Value::Object(attributes.chain(Some(text)).collect())
+            }
+        } else {
+            let mut m: Map<String, Value> = attributes.collect();
+            m.extend(self.children);
+            Value::Object(m)
+        }
+    }
+}
+
+fn local_name(name: &[u8]) -> String {
+    let name = String::from_utf8_lossy(name);
+    match name.find(':') {
+        Some(i) => name[i + 1..].to_string(),
+        None => name.to_string(),
+    }
+}
+
+fn parse_text(text: &str) -> Value {
+    if let Some(n) = text.parse::<f64>().ok().and_then(Number::from_f64) {
+        return Value::Number(n);
+    }
+    match text.parse::<bool>() {
+        Ok(b) => Value::Bool(b),
+        Err(_) => Value::String(text.to_string()),
+    }
+}
+
+fn unreadable(conf: &VersionSetting, position: usize, e: quick_xml::Error) -> Vec<ConfigError> {
+    vec![ConfigError::Unreadable {
+        file: conf.path.clone(),
+        error: format!("{} at byte {}", e, position),
+    }]
+}
+
+// only the DataType and Symbol elements are ever held as a tree, one at a time. the types
+// come before the symbols, so the ones nothing refers to are dropped at the end
+pub fn read_tpy<R: BufRead>(
+    r: R,
+    conf: &VersionSetting,
+) -> Result<(Vec<(Symbol, u32)>, HashMap<String, AdsType>), Vec<ConfigError>> {
+    let mut reader = Reader::from_reader(r);
+    let mut buf = Vec::new();
+    let mut path: Vec<String> = Vec::new();
+    let mut nodes: Vec<Node> = Vec::new();
+    let mut symbols = Vec::new();
+    let mut map = HashMap::new();
+    loop {
+        let event = match reader.read_event(&mut buf) {
+            Ok(event) => event,
+            Err(e) => return Err(unreadable(conf, reader.buffer_position(), e)),
+        };
+        match event {
+            Event::Start(ref e) => {
+                let name = local_name(e.name());
+                if !nodes.is_empty() || is_item(&path, &name) {
+                    nodes.push(Node::new(&reader, e));
+                }
+                path.push(name);
+            }
+            Event::Empty(ref e) => {
+                let name = local_name(e.name());
+                if let Some(parent) = nodes.last_mut() {
+                    let node = Node::new(&reader, e);
+                    parent.push(name, node.into_value());
+                }
+            }
+            Event::End(_) => {
+                path.pop();
+                if let Some(node) = nodes.pop() {
+                    let name = node.name.clone();
+                    let value = node.into_value();
+                    match nodes.last_mut() {
+                        Some(parent) => parent.push(name, value),
+                        None if name == "Symbol" => symbols.push(tpy_symbol(&value)),
+                        None => map.extend(tpy_data_type(&value)),
+                    }
+                }
+            }
+            Event::Text(ref t) => {
+                if let Some(node) = nodes.last_mut() {
+                    let text = t
+                        .unescape_and_decode(&reader)
+                        .map_err(|e| unreadable(conf, reader.buffer_position(), e))?;
+                    node.text += &text;
+                }
+            }
+            Event::CData(ref t) => {
+                if let Some(node) = nodes.last_mut() {
+                    node.text += &reader.decode(t);
+                }
+            }
+            Event::Eof => break,
+            _ => (),
+        }
+        buf.clear();
+    }
+    let map = reachable(conf, &symbols, map)?;
+    Ok((symbols, map))
+}
+
+fn is_item(path: &[String], name: &str) -> bool {
+    match (path.len(), path.last().map(|p| p.as_str()), name) {
+        (2, Some("DataTypes"), "DataType") | (2, Some("Symbols"), "Symbol") => true,
+        _ => false,
+    }
+}
+
+// the name of the first element, to tell a tpy from a tmc
+pub fn root_name<R: BufRead>(r: R) -> Option<String> {
+    let mut reader = Reader::from_reader(r);
+    let mut buf = Vec::new();
+    loop {
+        match reader.read_event(&mut buf) {
+            Ok(Event::Start(ref e)) | Ok(Event::Empty(ref e)) => {
+                return Some(local_name(e.name()))
+            }
+            Ok(Event::Eof) | Err(_) => return None,
+            _ => buf.clear(),
+        }
+    }
+}
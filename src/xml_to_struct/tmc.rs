@@ -0,0 +1,189 @@
+use serde_json::{Map, Value};
+use std::collections::HashMap;
+use types::helper::{fit_string, number_from_value, type_from_value};
+use types::{AdsPlcType, AdsType, Metadata, Name, Symbol};
+
+// index groups of the plc data areas for files that name none, symbol offsets are relative
+// to them
+const INPUTS: u32 = 0xF020;
+const OUTPUTS: u32 = 0xF030;
+const INTERNAL: u32 = 0x4040;
+
+// decimal like the tpy or hex as 0x4040 and #x4040
+fn address(v: &Value) -> Option<u32> {
+    match v {
+        Value::Number(ref n) => n.as_u64().map(|n| n as u32),
+        Value::String(ref s) => {
+            let s = s.trim();
+            if s.starts_with("0x") || s.starts_with("#x") {
+                u32::from_str_radix(&s[2..], 16).ok()
+            } else {
+                s.parse().ok()
+            }
+        }
+        _ => None,
+    }
+}
+
+fn as_vec(v: &Value) -> Vec<Value> {
+    match v {
+        Value::Array(ref a) => a.clone(),
+        Value::Null => Vec::new(),
+        v => vec![v.clone()],
+    }
+}
+
+// the tmc is brought into the shape of the tpy so the types are read the same way
+pub fn read_tmc(e: &Value) -> (Vec<(Symbol, u32)>, HashMap<String, AdsType>) {
+    let root = normalize(&e["TcModuleClass"]);
+    let map = as_vec(&root["DataTypes"]["DataType"])
+        .iter()
+        .filter(|f| f.get("BitSize").is_some())
+        .filter_map(|f| {
+            let name: Name = (&f["Name"]).into();
+            let key = name.decoration.unwrap_or(name.text);
+            AdsType::from_value(f).map(|ty| (key, ty))
+        })
+        .collect();
+    let mut symbols = Vec::new();
+    for module in as_vec(&root["Modules"]["Module"]) {
+        for area in as_vec(&module["DataAreas"]["DataArea"]) {
+            let area_group = match area["AreaNo"]["@AreaType"].as_str() {
+                Some(t) if t.starts_with("Input") => INPUTS,
+                Some(t) if t.starts_with("Output") => OUTPUTS,
+                _ => INTERNAL,
+            };
+            let area_group = address(&area["IGroup"])
+                .or_else(|| address(&area["AreaNo"]["@IGroup"]))
+                .unwrap_or(area_group);
+            for s in as_vec(&area["Symbol"]) {
+                let bit_size = number_from_value(&s["BitSize"]);
+                let bit_offs: u32 = number_from_value(&s["BitOffs"]);
+                let index_group = address(&s["IGroup"]).unwrap_or(area_group);
+                let index_offset = address(&s["IOffset"]).unwrap_or(bit_offs / 8);
+                let ty = if s["Type"].get("@Pointer").is_some() {
+                    AdsPlcType::Pointer(bit_size as usize / 8)
+                } else {
+                    fit_string(type_from_value(&s["Type"], None), bit_size)
+                };
+                let symbol = Symbol {
+                    index_group,
+                    index_offset,
+                    name: (&s["Name"]).into(),
+                    ty,
+                    metadata: Metadata::from_value(&s),
+                };
+                symbols.push((symbol, bit_size));
+            }
+        }
+    }
+    (symbols, map)
+}
+
+// BaseType becomes Type, GUIDs become decorations and SubRange becomes SubRangeInfo
+fn normalize(v: &Value) -> Value {
+    match v {
+        Value::Object(ref o) => Value::Object(
+            o.iter()
+                .map(|(k, v)| match k.as_str() {
+                    "BaseType" | "Type" => ("Type".to_string(), type_ref(v)),
+                    "@GUID" => ("@Decoration".to_string(), v.clone()),
+                    "@PointerTo" | "@ReferenceTo" => ("@Pointer".to_string(), v.clone()),
+                    "SubRange" => ("SubRangeInfo".to_string(), normalize(v)),
+                    k => (k.to_string(), normalize(v)),
+                })
+                .collect(),
+        ),
+        Value::Array(ref a) => Value::Array(a.iter().map(normalize).collect()),
+        v => v.clone(),
+    }
+}
+
+// types without a GUID are referenced by their name
+fn type_ref(v: &Value) -> Value {
+    let mut ty = match normalize(v) {
+        Value::Object(o) => o,
+        Value::String(s) => {
+            let mut o = Map::new();
+            o.insert("#text".to_string(), Value::String(s));
+            o
+        }
+        v => return v,
+    };
+    let text = ty.get("#text").and_then(|t| t.as_str()).map(|t| t.to_string());
+    if let Some(text) = text {
+        if text.starts_with("POINTER TO") || text.starts_with("REFERENCE TO") {
+            ty.insert("@Pointer".to_string(), Value::Bool(true));
+        }
+        if !ty.contains_key("@Decoration") {
+            ty.insert("@Decoration".to_string(), Value::String(text));
+        }
+    }
+    Value::Object(ty)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::read_version;
+    use serde_json;
+    use settings::VersionSetting;
+    use types::{AdsPlcType, AdsType, AdsVersion, Symbol};
+
+    const MOTOR: &str = "{2F6A1C44-0002-4A3B-9E21-7D0C5B8E1A01}";
+    const MODE: &str = "{2F6A1C44-0001-4A3B-9E21-7D0C5B8E1A01}";
+
+    fn conf(fixture: &str) -> VersionSetting {
+        let path = format!("{}/tests/fixtures/{}", env!("CARGO_MANIFEST_DIR"), fixture);
+        let conf = json!({"path": path, "all_symbols": true, "roots": [{"name": "MAIN"}]});
+        serde_json::from_value(conf).unwrap()
+    }
+
+    fn version() -> AdsVersion {
+        read_version(&conf("module.tmc")).unwrap()
+    }
+
+    fn symbol(version: &AdsVersion, name: &str) -> Symbol {
+        version.symbols.get(&name.to_string()).unwrap().clone()
+    }
+
+    #[test]
+    fn types_are_keyed_by_guid() {
+        let version = version();
+        match version.map.get(&MOTOR.to_string()).map(|t| t.clone()) {
+            Some(AdsType::Struct { properties, .. }) => {
+                let offsets: Vec<u32> = properties.iter().map(|p| p.bit_offs).collect();
+                assert_eq!(offsets, vec![0, 16, 32]);
+                assert_eq!(properties[0].ty, AdsPlcType::Bool);
+                match properties[1].ty {
+                    AdsPlcType::Other { ref reference, .. } => assert_eq!(reference, MODE),
+                    ref ty => panic!("eMode is {:?}", ty),
+                }
+            }
+            t => panic!("ST_Motor is {:?}", t),
+        }
+        match version.map.get(&MODE.to_string()).map(|t| t.clone()) {
+            Some(AdsType::Enum { keys, .. }) => {
+                assert_eq!(keys, vec![(0, "eOff".to_string()), (2, "eAuto".to_string())])
+            }
+            t => panic!("E_Mode is {:?}", t),
+        }
+    }
+
+    #[test]
+    fn data_areas_give_the_index_groups() {
+        let version = version();
+        let main = symbol(&version, "MAIN");
+        assert_eq!((main.index_group, main.index_offset), (0x4040, 32));
+        let io = symbol(&version, "IO");
+        assert_eq!((io.index_group, io.index_offset), (0xF020, 1));
+        let gvl = symbol(&version, "GVL");
+        assert_eq!((gvl.index_group, gvl.index_offset), (0x4020, 16));
+        assert_eq!(version.map.get(&"MAIN".to_string()).unwrap().len(), 10);
+    }
+
+    #[test]
+    fn broken_and_missing_files_are_errors() {
+        assert!(read_version(&conf("broken.tmc")).is_err());
+        assert!(read_version(&conf("missing.tmc")).is_err());
+    }
+}